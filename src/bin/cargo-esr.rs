@@ -9,40 +9,252 @@
     file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
 */
 
-use clap::{App, ArgGroup};
+use clap::{App, AppSettings, ArgGroup, ArgMatches, Error, ErrorKind};
 use clap::load_yaml;
 
-use cargo_esr::esr_crate::CrateSearch;
-use cargo_esr::esr_score::Scores;
+use cargo_esr::esr_crate::{CrateSearch, CrateCategories, Owner};
+use cargo_esr::esr_deps::LocalDeps;
+use cargo_esr::esr_policy::Policy;
+use cargo_esr::esr_cache::{self, CacheMode};
+use cargo_esr::esr_ratelimit;
+use cargo_esr::esr_gitlab::{self, GitLabConfig};
+use cargo_esr::esr_github::GitHubProvider;
+use cargo_esr::esr_repo::{self, RepoSource};
+use cargo_esr::esr_index;
+use cargo_esr::esr_history;
+use cargo_esr::esr_registry;
+use cargo_esr::esr_config::{self, Config};
+use cargo_esr::esr_score::{Scores, SortBy};
 use cargo_esr::esr_printer::EsrPrinter;
+use cargo_esr::esr_weights::{self, Weights};
 
 use std::env;
+use std::path::Path;
 
 const LIMIT_LOW: usize = 5;
 const LIMIT_HIGH: usize = 100;
 
-fn check_limit(limit: &str) -> usize {
+// None if out of range or invalid, after printing why
+fn check_limit(limit: &str) -> Option<usize> {
     match str::parse::<usize>(limit) {
         Ok(limit_num) => {
             let ll = LIMIT_LOW;
             let lh = LIMIT_HIGH;
             if limit_num < ll || limit_num > lh {
                 EsrPrinter::limit_out_of_range(limit_num, ll, lh).println();
-                std::process::exit(1);
+                None
             } else {
-                limit_num
+                Some(limit_num)
             }
         },
         Err(_) => {
             EsrPrinter::limit_invalid(limit).println();
-            std::process::exit(1);
+            None
         },
     }
 }
 
+// Options shared by the main command and subcommands
+struct Opts {
+    gh_token: String,
+    crate_only: bool,
+    repo_only: bool,
+    sort_positive: bool,
+    json: bool,
+}
+
+// None if it failed, after printing why
+fn local_deps(manifest_path: &Path, transitive: bool, opts: &Opts) -> Option<LocalDeps> {
+    match LocalDeps::from_manifest_path(manifest_path, transitive) {
+        Ok(local_deps) => Some(local_deps),
+        Err(ref e) if opts.json => {
+            println!("{}", Scores::error_json(&manifest_path.to_string_lossy(), e));
+            None
+        },
+        Err(ref e) => {
+            EsrPrinter::deps_failed(manifest_path, e).println();
+            None
+        },
+    }
+}
+
+async fn run_deps(sub_m: &ArgMatches<'_>, opts: &Opts) -> i32 {
+    let manifest_path = Path::new(sub_m.value_of("manifest-path").unwrap_or("Cargo.toml"));
+    let transitive = sub_m.is_present("transitive");
+    let local_deps = match local_deps(manifest_path, transitive, opts) {
+        Some(local_deps) => local_deps,
+        None => return 1,
+    };
+
+    let deps = local_deps.get_deps();
+    let ids = local_deps.get_ids();
+    let scores_res = Scores::collect_scores_from_ids(&ids, &opts.gh_token, opts.crate_only, opts.repo_only).await;
+
+    match opts.json {
+        true => println!("{}", Scores::deps_results_json(deps, &scores_res, opts.sort_positive)),
+        false => Scores::deps_results(deps, &scores_res, opts.sort_positive).println(),
+    }
+
+    0
+}
+
+async fn run_check(sub_m: &ArgMatches<'_>, opts: &Opts) -> i32 {
+    let policy_path = Path::new(sub_m.value_of("policy").unwrap_or("esr-policy.toml"));
+    let policy = match Policy::from_path(policy_path) {
+        Ok(policy) => policy,
+        Err(ref e) if opts.json => {
+            println!("{}", Scores::error_json(&policy_path.to_string_lossy(), e));
+            return 1;
+        },
+        Err(ref e) => {
+            EsrPrinter::policy_failed(policy_path, e).println();
+            return 1;
+        },
+    };
+
+    let manifest_path = Path::new(sub_m.value_of("manifest-path").unwrap_or("Cargo.toml"));
+    let local_deps = match local_deps(manifest_path, false, opts) {
+        Some(local_deps) => local_deps,
+        None => return 1,
+    };
+
+    let ids = local_deps.get_ids();
+    let scores_res = Scores::collect_scores_from_ids(&ids, &opts.gh_token, opts.crate_only, opts.repo_only).await;
+    let report = policy.check(&scores_res, opts.crate_only);
+
+    match opts.json {
+        true => println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default()),
+        false => EsrPrinter::policy_report(&report).println(),
+    }
+
+    report.exit_code()
+}
+
+async fn run_compare(sub_m: &ArgMatches<'_>, opts: &Opts) -> i32 {
+    let ids: Vec<_> = sub_m.values_of("crates")
+        .map(|crates| crates.map(String::from).collect())
+        .unwrap_or_default();
+
+    let scores_res = Scores::collect_scores_from_ids(&ids, &opts.gh_token, opts.crate_only, opts.repo_only).await;
+
+    match opts.json {
+        true => println!("{}", Scores::compare_results_json(&scores_res)),
+        false => Scores::compare_results(&scores_res).println(),
+    }
+
+    0
+}
+
+async fn run_owner(sub_m: &ArgMatches<'_>, opts: &Opts) -> i32 {
+    let login = sub_m.value_of("login").unwrap_or_default();
+
+    let owner_and_crates = match Owner::from_login(login).await {
+        Ok(owner) => owner.crates().await.map(|crates| (owner, crates)),
+        Err(e) => Err(e),
+    };
+
+    let (owner, owner_crates) = match owner_and_crates {
+        Ok(owner_and_crates) => owner_and_crates,
+        Err(ref e) if opts.json => {
+            println!("{}", Scores::error_json(login, e));
+            return 1;
+        },
+        Err(ref e) => {
+            EsrPrinter::owner_failed(login, e).println();
+            return 1;
+        },
+    };
+
+    let crates = owner_crates.get_crates();
+    if crates.is_empty() && !opts.json {
+        EsrPrinter::owner_no_crates(login).println();
+        return 1;
+    }
+
+    let scores_res = Scores::collect_scores(crates, &opts.gh_token, opts.crate_only, opts.repo_only).await;
+
+    match opts.json {
+        true => println!("{}", Scores::owner_results_json(&owner, &scores_res, crates, opts.sort_positive)),
+        false => Scores::owner_results(&owner, &scores_res, crates, opts.sort_positive).println(),
+    }
+
+    0
+}
+
+fn run_history(sub_m: &ArgMatches<'_>, json: bool) -> i32 {
+    let name = sub_m.value_of("name").unwrap_or_default();
+    match esr_history::history(name) {
+        Ok(ref snapshots) if json => {
+            println!("{}", serde_json::to_string_pretty(snapshots).unwrap_or_default());
+            0
+        },
+        Ok(ref snapshots) if snapshots.is_empty() => {
+            EsrPrinter::history_empty(name).println();
+            1
+        },
+        Ok(ref snapshots) => {
+            EsrPrinter::history(name, snapshots).println();
+            0
+        },
+        Err(ref e) => {
+            EsrPrinter::history_failed(e).println();
+            1
+        },
+    }
+}
+
+async fn run_categories(json: bool) -> i32 {
+    match CrateCategories::from_registry().await {
+        Ok(ref categories) if json => {
+            println!("{}", serde_json::to_string_pretty(categories.get_categories()).unwrap_or_default());
+            0
+        },
+        Ok(ref categories) => {
+            EsrPrinter::categories(categories.get_categories()).println();
+            0
+        },
+        Err(ref e) => {
+            EsrPrinter::categories_failed(e).println();
+            1
+        },
+    }
+}
 
+fn run_cache(sub_m: &ArgMatches<'_>, json: bool) -> i32 {
+    match sub_m.subcommand_name() {
+        Some("stats") => match esr_cache::stats() {
+            Ok(ref stats) if json => {
+                println!("{}", serde_json::to_string_pretty(stats).unwrap_or_default());
+                0
+            },
+            Ok(ref stats) => {
+                EsrPrinter::cache_stats(esr_cache::cache_dir().as_deref(), stats).println();
+                0
+            },
+            Err(ref e) => {
+                EsrPrinter::cache_failed(e).println();
+                1
+            },
+        },
+        Some("clear") => match esr_cache::clear() {
+            Ok(removed) => {
+                EsrPrinter::cache_cleared(removed).println();
+                0
+            },
+            Err(ref e) => {
+                EsrPrinter::cache_failed(e).println();
+                1
+            },
+        },
+        _ => {
+            println!("{}", sub_m.usage());
+            1
+        },
+    }
+}
 
-async fn run() {
+// The exit code
+async fn run() -> i32 {
     // clap
     let mut args: Vec<_> = env::args().collect();
 
@@ -66,15 +278,26 @@ async fn run() {
         .required(false);
 
     let clap_app = App::from_yaml(yaml)
+        .setting(AppSettings::SubcommandsNegateReqs)
         .group(search_or_score)
         .group(search_by)
         .group(score_filter);
 
     let m = clap_app.get_matches_from(args);
 
-    let crate_only = m.is_present("crate-only");
-    let repo_only = m.is_present("repo-only");
-    let sort_positive = m.is_present("sort-positive");
+    // Global args are propagated down to subcommands
+    let opts_m = m.subcommand().1.unwrap_or(&m);
+
+    // Conflicts are only checked between args passed at the same level
+    if opts_m.is_present("index-url") && opts_m.is_present("registry") {
+        Error::with_description("The argument '--index-url <URL>' cannot be used with '--registry <NAME>'",
+                                ErrorKind::ArgumentConflict).exit();
+    }
+
+    let crate_only = opts_m.is_present("crate-only");
+    let repo_only = opts_m.is_present("repo-only");
+    let sort_positive = opts_m.is_present("sort-positive");
+    let json = opts_m.value_of("format") == Some("json");
     let results_limit = m.value_of("results-limit").unwrap_or("10");
     let search_limit = m.value_of("search-limit").unwrap_or("25");
 
@@ -82,7 +305,17 @@ async fn run() {
     let search_by_recent_downloads = m.is_present("search-by-recent-downloads");
     let search_by_total_downloads = m.is_present("search-by-total-downloads");
 
-    if m.is_present("debug") {
+    let category = m.value_of("category");
+    let keywords: Vec<_> = m.values_of("keyword").map(|k| k.collect()).unwrap_or_default();
+
+    let sort_by = match m.value_of("sort-by") {
+        Some("downloads") => SortBy::Downloads,
+        Some("recent-downloads") => SortBy::RecentDownloads,
+        Some("exact-match") => SortBy::ExactMatch,
+        _ => SortBy::Score,
+    };
+
+    if opts_m.is_present("debug") {
         let _logger_setup = fern::Dispatch::new()
             .format(|out, message, _| {
                 out.finish(format_args!(
@@ -99,32 +332,129 @@ async fn run() {
 
         if _logger_setup.is_err() {
             EsrPrinter::err("Logger setup failed.");
-            std::process::exit(1);
+            return 1;
+        }
+    }
+
+    let config_res = Config::from_path_or_config(opts_m.value_of("config").map(Path::new))
+        .and_then(esr_config::set_config);
+
+    if let Err(ref e) = config_res {
+        EsrPrinter::config_failed(e).println();
+        return 1;
+    }
+
+    let weights_res = Weights::from_path_or_config(opts_m.value_of("weights").map(Path::new))
+        .and_then(esr_weights::set_weights);
+
+    if let Err(ref e) = weights_res {
+        EsrPrinter::weights_failed(e).println();
+        return 1;
+    }
+
+    let results_limit_num = match check_limit(results_limit) {
+        Some(results_limit_num) => results_limit_num,
+        None => return 1,
+    };
+    if check_limit(search_limit).is_none() {
+        return 1;
+    }
+
+    let cache_mode = match (opts_m.is_present("no-cache"), opts_m.is_present("refresh"), opts_m.is_present("offline")) {
+        (true, _, _) => CacheMode::Disabled,
+        (_, true, _) => CacheMode::Refresh,
+        (_, _, true) => CacheMode::Offline,
+        (false, false, false) => CacheMode::Normal,
+    };
+
+    // Can't fail, as it's only set here
+    let _ = esr_cache::set_cache_mode(cache_mode);
+
+    if let Some(index_url) = opts_m.value_of("index-url") {
+        // Can't fail, as it's only set here
+        let _ = esr_index::set_sparse_index_url(index_url);
+    }
+
+    if let Some(registry) = opts_m.value_of("registry") {
+        if let Err(ref e) = esr_registry::use_registry(registry).await {
+            EsrPrinter::registry_failed(registry, e).println();
+            return 1;
         }
     }
 
-    let results_limit_num = check_limit(results_limit);
-    let _search_limit_num = check_limit(search_limit);
+    let gitlab_token = opts_m.value_of("gitlab-token")
+        .map(String::from)
+        .or_else(|| env::var("CARGO_ESR_GITLAB_TOKEN").ok());
+    let gitlab_url = opts_m.value_of("gitlab-url").unwrap_or(esr_gitlab::DEFAULT_GITLAB_URL);
+
+    // Can't fail, as it's only set here
+    let _ = esr_gitlab::set_gitlab_config(GitLabConfig::new(gitlab_url, gitlab_token));
+
+    let repo_source = match opts_m.value_of("repo-source") {
+        Some("git") => RepoSource::Git,
+        _ => RepoSource::Api,
+    };
+
+    // Can't fail, as it's only set here
+    let _ = esr_repo::set_repo_source(repo_source);
+
+    // Can't fail, as it's only set here
+    let _ = esr_history::set_recording(opts_m.is_present("record"));
+
+    // Subcommands that don't need a token
+    match m.subcommand() {
+        ("cache", Some(sub_m)) => return run_cache(sub_m, json),
+        ("history", Some(sub_m)) => return run_history(sub_m, json),
+        ("categories", Some(_)) => return run_categories(json).await,
+        _ => (),
+    }
 
     let mut gh_token = String::with_capacity(48);
-    if m.value_of("gh-score").is_some() || !crate_only {
-        if let Some(arg_token) = m.value_of("gh-token") {
+    // Only GitHub repos need a GitHub token, and only from the API
+    let needs_gh_token = match (repo_source, m.value_of("gh-score")) {
+        (RepoSource::Git, _) => false,
+        (RepoSource::Api, Some(repo)) => esr_repo::detect_provider(repo, String::new())
+            .map(|(provider, _)| provider.name() == GitHubProvider::NAME)
+            .unwrap_or(true),
+        (RepoSource::Api, None) => !crate_only,
+    };
+
+    if needs_gh_token {
+        if let Some(arg_token) = opts_m.value_of("gh-token") {
             gh_token.push_str(arg_token);
         } else if let Ok(env_token) = std::env::var("CARGO_ESR_GH_TOKEN") {
             gh_token.push_str(&env_token);
+        } else if esr_cache::is_offline() {
+            // Not used, as cached responses are keyed without tokens
         } else {
             EsrPrinter::no_token().println();
-            std::process::exit(1);
+            return 1;
         }
     }
 
+    if let (subcommand, Some(sub_m)) = m.subcommand() {
+        let opts = Opts { gh_token, crate_only, repo_only, sort_positive, json };
+        return match subcommand {
+            "deps" => run_deps(sub_m, &opts).await,
+            "check" => run_check(sub_m, &opts).await,
+            "compare" => run_compare(sub_m, &opts).await,
+            "owner" => run_owner(sub_m, &opts).await,
+            _ => unreachable!(),
+        };
+    }
+
     match (m.value_of("gh-score"), m.value_of("score"), m.values_of("search")) {
         (Some(repo_path), _, _)  => {
             match Scores::from_repo_with_token(repo_path.into(), gh_token).await {
+                Ok(ref repo_scores) if json => println!("{}", repo_scores.detailed_scores_json(repo_path)),
                 Ok(repo_scores) => repo_scores.detailed_scores().println(),
+                Err(ref e) if json => {
+                    println!("{}", Scores::error_json(repo_path, e));
+                    return 1;
+                },
                 Err(ref e) => {
                     EsrPrinter::repo_no_score(repo_path, e).println();
-                    std::process::exit(1);
+                    return 1;
                 },
             }
         },
@@ -137,10 +467,15 @@ async fn run() {
             };
 
             match crates_scores_res {
+                Ok(ref crate_scores) if json => println!("{}", crate_scores.detailed_scores_json(crate_name)),
                 Ok(crate_scores) => crate_scores.detailed_scores().println(),
+                Err(ref e) if json => {
+                    println!("{}", Scores::error_json(crate_name, e));
+                    return 1;
+                },
                 Err(ref e) => {
                     EsrPrinter::crate_no_score(crate_name, e).println();
-                    std::process::exit(1);
+                    return 1;
                 },
             }
         },
@@ -166,32 +501,53 @@ async fn run() {
                     (true, _, _)      => "per_page=".to_string() + search_limit + "&q=" + &*search_str,
                 },
 
-            };
+            } + &*CrateSearch::filter_params(category, &keywords);
 
             match CrateSearch::from_id_single_page(&search_args).await {
                 Ok(search) => {
                     let crates = search.get_crates();
 
-                    if crates.is_empty() {
+                    if crates.is_empty() && json {
+                        println!("[]");
+                        return 1;
+                    } else if crates.is_empty() {
                         EsrPrinter::search_no_results(&search_str).println();
-                        std::process::exit(1);
+                        return 1;
                     }
 
                     let crates_scores_res = Scores::collect_scores(crates, &gh_token, crate_only, repo_only).await;
-                    Scores::search_results(&*crates_scores_res, sort_positive, results_limit_num).println();
+                    match json {
+                        true => println!("{}", Scores::search_results_json(&*crates_scores_res, crates, sort_by, sort_positive, results_limit_num)),
+                        false => Scores::search_results(&*crates_scores_res, crates, sort_by, sort_positive, results_limit_num).println(),
+                    }
+                },
+                Err(ref e) if json => {
+                    println!("{}", Scores::error_json(&search_str, e));
+                    return 1;
                 },
                 Err(ref e) => {
                     EsrPrinter::search_failed(&search_str, e).println();
-                    std::process::exit(1);
+                    return 1;
                 }
             }
         },
 
         (_, _, _) => unreachable!(),
     }
+
+    0
 }
 
 fn main() {
     env::set_var("SMOL_THREADS", "32");
-    smol::block_on(run());
+    let exit_code = smol::block_on(run());
+
+    // Remaining budget of rate limited APIs (e.g. GitHub's), whether
+    // scoring succeeded or not
+    let rate_limits = esr_ratelimit::rate_limits();
+    if !rate_limits.is_empty() {
+        EsrPrinter::rate_limits(&rate_limits).eprintln();
+    }
+
+    std::process::exit(exit_code);
 }
@@ -0,0 +1,342 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::fs;
+use std::io::Write;
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use crate::esr_errors::{Result, EsrError};
+
+const HOUR: u64 = 3600;
+
+// (Endpoint path pattern, TTL). First match wins. Hosts are not matched, so
+// alternative registries and self-hosted forges get the same TTLs.
+const TTLS: &[(&str, u64)] = &[
+    // Registry searches
+    ("/api/v1/crates?", HOUR),
+    ("/owners", 24 * HOUR),
+    ("/api/v1/crates/", 6 * HOUR),
+    // GitHub and Gitea repos
+    ("/repos/", 6 * HOUR),
+    // GitLab projects
+    ("/api/v4/projects/", 6 * HOUR),
+    // GitHub GraphQL results, cached per repo
+    ("/graphql?repo=", 6 * HOUR),
+];
+
+const DEFAULT_TTL: u64 = HOUR;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    // Read fresh entries, write new ones
+    Normal,
+    // Don't read, but write new entries
+    Refresh,
+    // Neither read nor write
+    Disabled,
+    // Read all entries regardless of age, never fetch or write
+    Offline,
+}
+
+static CACHE_MODE: OnceCell<CacheMode> = OnceCell::new();
+
+pub fn get_cache_mode() -> CacheMode {
+    // Tests never read or write the user's cache
+    *CACHE_MODE.get_or_init(|| match cfg!(test) {
+        true => CacheMode::Disabled,
+        false => CacheMode::Normal,
+    })
+}
+
+pub fn set_cache_mode(mode: CacheMode) -> Result<()> {
+    CACHE_MODE.set(mode)
+        .map_err(|_| EsrError::from("Cache mode already set"))
+}
+
+pub fn is_offline() -> bool {
+    get_cache_mode() == CacheMode::Offline
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cargo-esr").join("http"))
+}
+
+// FNV-1a. Stable across runs and Rust versions, unlike `DefaultHasher`.
+pub(crate) fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// The URL without tokens, which are added as query params
+pub(crate) fn cache_key(url: &str) -> String {
+    let (base, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i+1..]),
+        None => return url.into(),
+    };
+
+    let query: Vec<_> = query
+        .split('&')
+        .filter(|param| !param.starts_with("access_token=") && !param.starts_with("private_token="))
+        .collect();
+
+    match query.is_empty() {
+        true => base.into(),
+        false => format!("{}?{}", base, query.join("&")),
+    }
+}
+
+// "/api/v1/crates?q=foo" from "https://crates.io/api/v1/crates?q=foo"
+fn endpoint_path(key: &str) -> &str {
+    let rest = key.find("://").map(|i| &key[i + 3..]).unwrap_or(key);
+    rest.find('/').map(|i| &rest[i..]).unwrap_or("")
+}
+
+fn ttl_for_key(key: &str) -> Duration {
+    let path = endpoint_path(key);
+    let ttl = TTLS.iter()
+        .find(|(pattern, _)| path.contains(pattern))
+        .map(|&(_, ttl)| ttl)
+        .unwrap_or(DEFAULT_TTL);
+    Duration::from_secs(ttl)
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{:016x}", fnv1a(key)))
+}
+
+// Entries are stored as the key, a new line, then the body
+fn read_entry(path: &Path) -> Option<(String, Vec<u8>)> {
+    let mut data = fs::read(path).ok()?;
+    let nl = data.iter().position(|&b| b == b'\n')?;
+    let body = data.split_off(nl + 1);
+    data.pop();
+    let key = String::from_utf8(data).ok()?;
+    Some((key, body))
+}
+
+fn entry_age(path: &Path, now: SystemTime) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    now.duration_since(modified).ok()
+}
+
+// The body of the entry of `key`, if it's not older than `max_age`
+fn read_fresh_entry(dir: &Path, key: &str, max_age: Option<Duration>, now: SystemTime) -> Option<Vec<u8>> {
+    let path = entry_path(dir, key);
+
+    if let Some(max_age) = max_age {
+        if entry_age(&path, now)? > max_age {
+            return None;
+        }
+    }
+
+    match read_entry(&path)? {
+        // Guard against hash collisions
+        (entry_key, body) if entry_key == key => Some(body),
+        _ => None,
+    }
+}
+
+fn write_entry(dir: &Path, key: &str, body: &[u8]) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    // Write to a temp file first, so concurrent readers never see partial entries
+    let path = entry_path(dir, key);
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(key.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.write_all(body)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+pub(crate) fn get(url: &str) -> Option<Vec<u8>> {
+    let mode = get_cache_mode();
+    if mode == CacheMode::Refresh || mode == CacheMode::Disabled {
+        return None;
+    }
+
+    // Stale data is better than no data when offline
+    let key = cache_key(url);
+    let max_age = match mode {
+        CacheMode::Normal => Some(ttl_for_key(&key)),
+        _ => None,
+    };
+
+    read_fresh_entry(&cache_dir()?, &key, max_age, SystemTime::now())
+}
+
+pub(crate) fn put(url: &str, body: &[u8]) {
+    let mode = get_cache_mode();
+    if mode == CacheMode::Disabled || mode == CacheMode::Offline {
+        return;
+    }
+
+    let key = cache_key(url);
+    let write = || -> Result<()> {
+        let dir = cache_dir().ok_or("Failed to get cache dir")?;
+        write_entry(&dir, &key, body)
+    };
+
+    if let Err(e) = write() {
+        log::debug!("Failed to cache data from '{}': {}", key, e);
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CacheStats {
+    entries: usize,
+    expired: usize,
+    size_bytes: u64,
+    // host => entries
+    hosts: BTreeMap<String, usize>,
+}
+
+impl CacheStats {
+    pub fn get_entries(&self) -> usize {
+        self.entries
+    }
+
+    pub fn get_expired(&self) -> usize {
+        self.expired
+    }
+
+    pub fn get_size_bytes(&self) -> u64 {
+        self.size_bytes
+    }
+
+    pub fn get_hosts(&self) -> &BTreeMap<String, usize> {
+        &self.hosts
+    }
+}
+
+fn cache_entries() -> Result<Vec<PathBuf>> {
+    let dir = match cache_dir() {
+        Some(ref dir) if dir.is_dir() => dir.clone(),
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut ret = Vec::with_capacity(256);
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            ret.push(path);
+        }
+    }
+    Ok(ret)
+}
+
+pub fn stats() -> Result<CacheStats> {
+    let mut stats = CacheStats::default();
+
+    for path in cache_entries()? {
+        let (key, _) = match read_entry(&path) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        stats.entries += 1;
+        stats.size_bytes += fs::metadata(&path)?.len();
+
+        if entry_age(&path, SystemTime::now()).map(|age| age > ttl_for_key(&key)).unwrap_or(true) {
+            stats.expired += 1;
+        }
+
+        let host = key.split('/').nth(2).unwrap_or("unknown").to_string();
+        *stats.hosts.entry(host).or_insert(0) += 1;
+    }
+
+    Ok(stats)
+}
+
+// Returns the number of removed entries
+pub fn clear() -> Result<usize> {
+    let entries = cache_entries()?;
+    for path in &entries {
+        fs::remove_file(path)?;
+    }
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_in_tests() {
+        assert_eq!(get_cache_mode(), CacheMode::Disabled);
+        assert!(set_cache_mode(CacheMode::Normal).is_err());
+        assert_eq!(get("https://crates.io/api/v1/crates/foo"), None);
+    }
+
+    #[test]
+    fn cache_keys_without_tokens() {
+        let repo = "https://api.github.com/repos/foo/bar";
+        assert_eq!(cache_key(repo), repo);
+        assert_eq!(cache_key(&format!("{}?access_token=secret", repo)), repo);
+        assert_eq!(cache_key(&format!("{}?per_page=100&access_token=secret&page=2", repo)),
+                   format!("{}?per_page=100&page=2", repo));
+        assert_eq!(cache_key("https://gitlab.com/api/v4/projects/1?private_token=secret&statistics=true"),
+                   "https://gitlab.com/api/v4/projects/1?statistics=true");
+        assert_eq!(cache_key("https://crates.io/api/v1/crates?q=access_token"),
+                   "https://crates.io/api/v1/crates?q=access_token");
+    }
+
+    #[test]
+    fn ttls_by_path() {
+        let hours = |key| ttl_for_key(key).as_secs() / HOUR;
+        assert_eq!(hours("https://crates.io/api/v1/crates?q=foo"), 1);
+        assert_eq!(hours("https://crates.io/api/v1/crates/foo/owners"), 24);
+        assert_eq!(hours("https://crates.io/api/v1/crates/foo"), 6);
+        assert_eq!(hours("https://corp.example/registry/api/v1/crates/foo"), 6);
+        assert_eq!(hours("https://api.github.com/repos/foo/bar"), 6);
+        assert_eq!(hours("https://gitea.example/api/v1/repos/foo/bar"), 6);
+        assert_eq!(hours("https://gitlab.com/api/v4/projects/foo%2Fbar"), 6);
+        assert_eq!(hours("https://api.github.com/graphql?repo=foo/bar"), 6);
+        // Hosts are not matched
+        assert_eq!(hours("https://repos.example/owners"), 24);
+        assert_eq!(hours("https://crates.io/api/v1/summary"), 1);
+    }
+
+    #[test]
+    fn put_get_and_expire() {
+        let dir = std::env::temp_dir().join(format!("esr-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let key = "https://crates.io/api/v1/crates/foo";
+        let now = SystemTime::now();
+        assert_eq!(read_fresh_entry(&dir, key, None, now), None);
+
+        write_entry(&dir, key, b"{\n\"crate\": {}\n}").unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let ttl = ttl_for_key(key);
+        assert_eq!(read_fresh_entry(&dir, key, Some(ttl), now).as_deref(), Some(&b"{\n\"crate\": {}\n}"[..]));
+
+        // Expired, unless stale entries are accepted
+        let later = now + ttl + Duration::from_secs(60);
+        assert_eq!(read_fresh_entry(&dir, key, Some(ttl), later), None);
+        assert!(read_fresh_entry(&dir, key, None, later).is_some());
+
+        // Overwritten in place
+        write_entry(&dir, key, b"new").unwrap();
+        assert_eq!(read_fresh_entry(&dir, key, None, now).as_deref(), Some(&b"new"[..]));
+
+        // An entry of a colliding key is not returned
+        fs::write(entry_path(&dir, key), b"https://crates.io/api/v1/crates/bar\nbar").unwrap();
+        assert_eq!(read_fresh_entry(&dir, key, None, now), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
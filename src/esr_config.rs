@@ -0,0 +1,189 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use serde::Deserialize;
+use once_cell::sync::OnceCell;
+
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_retry::RetryPolicy;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+// Get the active config. An empty config is used if `set_config()` was never called.
+pub fn get_config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+pub fn set_config(config: Config) -> Result<()> {
+    CONFIG.set(config)
+        .map_err(|_| EsrError::from("Config already set"))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GiteaConfig {
+    // Self-hosted Gitea/Forgejo instances, in addition to codeberg.org.
+    // Either hosts (e.g. "git.example.com"), or base URLs (e.g. "http://localhost:3000").
+    #[serde(default)]
+    hosts: Vec<String>,
+}
+
+impl GiteaConfig {
+    pub fn get_hosts(&self) -> &[String] {
+        &self.hosts
+    }
+}
+
+// Max pages of 100 items fetched from GitHub list endpoints
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitHubPages {
+    contributors: usize,
+    pull_requests: usize,
+}
+
+impl Default for GitHubPages {
+    fn default() -> Self {
+        Self {
+            contributors: 1,
+            pull_requests: 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GitHubBackend {
+    // A few REST requests per repo
+    Rest,
+    // Batched queries, with contributors counted over recent commits
+    GraphQl,
+}
+
+impl Default for GitHubBackend {
+    fn default() -> Self {
+        GitHubBackend::Rest
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitHubGraphQl {
+    // Can point to a local stub serving recorded responses
+    endpoint: String,
+    // Max repos per query
+    batch_size: usize,
+    // Pages of 100 default branch commits contributors are counted over
+    history_pages: usize,
+}
+
+impl Default for GitHubGraphQl {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://api.github.com/graphql".into(),
+            batch_size: 10,
+            history_pages: 3,
+        }
+    }
+}
+
+impl GitHubGraphQl {
+    pub fn get_endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn get_batch_size(&self) -> usize {
+        self.batch_size.max(1)
+    }
+
+    pub fn get_history_pages(&self) -> usize {
+        self.history_pages.max(1)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GitHubConfig {
+    #[serde(default)]
+    backend: GitHubBackend,
+    #[serde(default)]
+    graphql: GitHubGraphQl,
+    #[serde(default)]
+    max_pages: GitHubPages,
+}
+
+impl GitHubConfig {
+    pub fn get_backend(&self) -> GitHubBackend {
+        self.backend
+    }
+
+    pub fn get_graphql(&self) -> &GitHubGraphQl {
+        &self.graphql
+    }
+
+    pub fn get_max_contributors_pages(&self) -> usize {
+        self.max_pages.contributors.max(1)
+    }
+
+    pub fn get_max_pull_requests_pages(&self) -> usize {
+        self.max_pages.pull_requests.max(1)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    github: GitHubConfig,
+    #[serde(default)]
+    gitea: GiteaConfig,
+    #[serde(default)]
+    retry: RetryPolicy,
+}
+
+impl Config {
+    pub fn from_toml_str(toml_str: &str) -> Result<Self> {
+        Ok(toml::from_str(toml_str)?)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    // Load config from `path` if passed. Otherwise, from the user's config
+    // dir (e.g. `~/.config/cargo-esr/config.toml`) if it exists.
+    pub fn from_path_or_config(path: Option<&Path>) -> Result<Self> {
+        match (path, Self::config_path()) {
+            (Some(path), _) => Self::from_path(path),
+            (None, Some(ref config_path)) if config_path.is_file() => Self::from_path(config_path),
+            (None, _) => Ok(Self::default()),
+        }
+    }
+
+    pub fn config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("cargo-esr").join("config.toml"))
+    }
+
+    pub fn get_github(&self) -> &GitHubConfig {
+        &self.github
+    }
+
+    pub fn get_gitea(&self) -> &GiteaConfig {
+        &self.gitea
+    }
+
+    pub fn get_retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+}
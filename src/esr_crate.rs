@@ -9,33 +9,24 @@
     file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
 */
 
-use crates_index::Index;
+use std::collections::{HashSet, BTreeMap};
+
 use semver::{Version, VersionReq};
-use serde::Deserialize;
+use chrono::{NaiveDate, Duration};
+use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
 use futures::future;
-use once_cell::sync::OnceCell;
 
 use crate::esr_errors::{Result, EsrError};
 use crate::esr_util;
+use crate::esr_retry;
+use crate::esr_index::{self, Dependant};
+use crate::esr_registry;
 use crate::esr_from::{Meta, EsrFrom, EsrFromMulti};
-use crate::esr_printer::EsrPrinter;
-
-fn get_index() -> Result<&'static Index> {
-        static INDEX: OnceCell<std::result::Result<Index, String>> = OnceCell::new();
-        let init = || {
-            EsrPrinter::crate_index_init().eprintln();
-            let index = Index::new_cargo_default();
-            index.retrieve_or_update()
-                .map_err(|e| e.to_string())
-                .map(|_| index)
-        };
-        INDEX.get_or_init(init)
-            .as_ref()
-            .map_err(|e| EsrError::from(&**e))
-}
+use crate::esr_score::ScoreRow;
+use crate::esr_weights::{self, Weights};
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct CrateGeneralInfo {
     id: String, // crate name!
     created_at: String,
@@ -44,15 +35,38 @@ pub struct CrateGeneralInfo {
     description: Option<String>,
     repository: Option<String>,
     documentation: Option<String>,
+    homepage: Option<String>,
+    #[serde(default)]
+    downloads: usize,
+    // Downloads in the last 90 days
+    recent_downloads: Option<usize>,
+    // Keyword/category IDs. Only in single crate responses (null in searches).
+    keywords: Option<Vec<String>>,
+    categories: Option<Vec<String>>,
+    // Only meaningful in search results
+    #[serde(default)]
+    exact_match: bool,
 }
 
 impl CrateGeneralInfo {
     pub fn get_id(&self) -> &str {
         &self.id
     }
+
+    pub fn get_downloads(&self) -> usize {
+        self.downloads
+    }
+
+    pub fn get_recent_downloads(&self) -> Option<usize> {
+        self.recent_downloads
+    }
+
+    pub fn is_exact_match(&self) -> bool {
+        self.exact_match
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct CrateReleaseInfo {
     created_at: String,
     downloads: usize,
@@ -68,17 +82,28 @@ struct UserInfo {
 
 // =====
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct CrateInfo {
     #[serde(rename = "crate")]
     general_info: CrateGeneralInfo,
     #[serde(rename = "versions")]
     releases: Vec<CrateReleaseInfo>,
+    // Reconstructed from the local crates index, in offline mode
+    #[serde(default, skip_deserializing)]
+    from_index: bool,
 }
 
+#[async_trait]
 impl EsrFrom for CrateInfo {
     fn url_from_id(id: &str) -> String {
-        String::from("https://crates.io/api/v1/crates/") + id
+        esr_registry::get_registry().api_url("crates/") + id
+    }
+
+    async fn from_id(id: &str) -> Result<Self> {
+        match Self::from_url(&*Self::url_from_id(id)).await {
+            Err(EsrError::Offline(_)) => Self::from_index(id.into()).await,
+            res => res,
+        }
     }
 }
 
@@ -90,48 +115,160 @@ struct CrateOwners {
 
 impl EsrFrom for CrateOwners {
     fn url_from_id(id: &str) -> String {
-        let url = String::from("https://crates.io/api/v1/crates/:id/owners");
+        let url = esr_registry::get_registry().api_url("crates/:id/owners");
         url.replace(":id", id)
     }
 }
 
 #[derive(Deserialize, Debug)]
-struct Dependant {
-    crate_name: String,
-    default_features: bool,
-    optional: bool,
-    req: String, // version required
-}
-
-impl Dependant {
-    async fn dependants_from_id(id: String) -> Result<Vec<Self>> {
-        log::debug!("Getting dependats from index for {}", id);
-        smol::spawn( async move {
-            let mut ret = Vec::with_capacity(64);
-            for cr in get_index()?.crates() {
-                let latest_version = cr.latest_version();
-                let match_opt = latest_version
-                    .dependencies()
-                    .iter()
-                    .find(|dep| !latest_version.is_yanked() && dep.crate_name() == id);
-
-                if let Some(dep) = match_opt {
-                    ret.push(
-                        Self {
-                            crate_name: cr.name().into(),
-                            default_features: dep.has_default_features() && !dep.is_optional(),
-                            optional: dep.is_optional(),
-                            req: dep.requirement().into(),
-                        }
-                    );
-                }
+struct DailyDownloads {
+    date: String, // e.g. "2021-03-01"
+    downloads: usize,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct CrateDownloadsMeta {
+    // Downloads of versions not in `version_downloads`
+    #[serde(default)]
+    extra_downloads: Vec<DailyDownloads>,
+}
+
+// Per-version daily downloads in the last 90 days
+#[derive(Deserialize, Debug)]
+struct CrateDownloads {
+    version_downloads: Vec<DailyDownloads>,
+    #[serde(default)]
+    meta: CrateDownloadsMeta,
+}
+
+impl EsrFrom for CrateDownloads {
+    fn url_from_id(id: &str) -> String {
+        let url = esr_registry::get_registry().api_url("crates/:id/downloads");
+        url.replace(":id", id)
+    }
+}
+
+// Days covered by the downloads endpoint, ending today
+const DOWNLOADS_DAYS: i64 = 90;
+
+impl CrateDownloads {
+    // Total downloads per day of the last 90 days ending `today`, oldest
+    // first. Days with no downloads are included as 0.
+    fn daily_totals(&self, today: NaiveDate) -> Vec<usize> {
+        let mut by_date = BTreeMap::new();
+        for dd in self.version_downloads.iter().chain(&self.meta.extra_downloads) {
+            if let Ok(date) = NaiveDate::parse_from_str(&dd.date, "%Y-%m-%d") {
+                *by_date.entry(date).or_insert(0) += dd.downloads;
             }
-            Ok(ret)
-        }).await
+        }
+
+        let first = today - Duration::days(DOWNLOADS_DAYS - 1);
+        (0..DOWNLOADS_DAYS)
+            .map(|day| *by_date.get(&(first + Duration::days(day))).unwrap_or(&0))
+            .collect()
     }
 }
 
+// Change in downloads of the last 30 days relative to the 30 days before,
+// between -100% and +100%.
+fn downloads_change_pct(daily: &[usize]) -> f64 {
+    let window_sum = |skip: usize| daily.iter().rev().skip(skip).take(30).sum::<usize>() as f64;
+    let (last, prev) = (window_sum(0), window_sum(30));
+
+    match (prev == 0.0, last == 0.0) {
+        (true, true) => 0.0,
+        (true, false) => 100.0,
+        (false, _) => ((last - prev) * 100.0 / prev).min(100.0),
+    }
+}
+
+async fn dependants_from_id(id: String) -> Result<Vec<Dependant>> {
+    if esr_index::is_sparse() {
+        log::debug!("Getting dependats from API for {}", id);
+        return esr_index::sparse_dependants(&id).await;
+    }
+
+    log::debug!("Getting dependats from index for {}", id);
+    smol::unblock(move || {
+        Ok(esr_index::get_reverse_deps()?.dependants(&id).to_vec())
+    }).await
+}
+
+// None with the sparse index, as walking the whole graph is not possible
+async fn transitive_dependants_from_id(id: String) -> Result<Option<Vec<String>>> {
+    if esr_index::is_sparse() {
+        return Ok(None);
+    }
+
+    log::debug!("Getting transitive dependats from index for {}", id);
+    smol::unblock(move || {
+        Ok(Some(esr_index::get_reverse_deps()?
+            .transitive_dependants(&id, None)
+            .into_iter()
+            .map(String::from)
+            .collect()))
+    }).await
+}
+
 impl CrateInfo {
+    // Only versions, yanked flags, and dependencies are available in the index.
+    // Dates, downloads, and metadata like the license are left empty.
+    async fn from_index(id: String) -> Result<Self> {
+        log::debug!("Reconstructing crate info from index for {}", id);
+        esr_retry::spawn(async move {
+            let versions = esr_index::index_versions(id.clone()).await?;
+            let name = versions.get(0)
+                .map(|ver| ver.get_name().to_string())
+                .ok_or_else(|| EsrError::Other(format!("crate \"{}\" not found in the index", id)))?;
+
+            // Newest first, like crates.io
+            let releases: Vec<_> = versions
+                .iter()
+                .rev()
+                .map(|ver| CrateReleaseInfo {
+                    created_at: String::new(),
+                    downloads: 0,
+                    num: ver.get_version().into(),
+                    yanked: ver.is_yanked(),
+                    license: None,
+                })
+                .collect();
+
+            // Highest non-yanked stable version, falling back to the highest version
+            let parsed: Vec<_> = releases.iter()
+                .filter_map(|r| Version::parse(&r.num).ok().map(|ver| (ver, r)))
+                .collect();
+            let max_version = parsed.iter()
+                .filter(|(ver, r)| !r.yanked && !ver.is_prerelease())
+                .max_by(|a, b| a.0.cmp(&b.0))
+                .or_else(|| parsed.iter().max_by(|a, b| a.0.cmp(&b.0)))
+                .map(|(ver, _)| ver.to_string())
+                .unwrap_or_else(|| "0.0.0".into());
+
+            let general_info = CrateGeneralInfo {
+                id: name,
+                created_at: String::new(),
+                updated_at: String::new(),
+                max_version,
+                description: None,
+                repository: None,
+                documentation: None,
+                homepage: None,
+                downloads: 0,
+                recent_downloads: None,
+                keywords: None,
+                categories: None,
+                exact_match: false,
+            };
+
+            Ok(Self { general_info, releases, from_index: true })
+        }).await
+    }
+
+    pub fn is_from_index(&self) -> bool {
+        self.from_index
+    }
+
     pub fn get_id(&self) -> &str {
         &self.general_info.id
     }
@@ -152,6 +289,13 @@ impl CrateInfo {
             .collect()
     }
 
+    pub fn non_yanked_versions(&self) -> Vec<String> {
+        self.non_yanked_releases()
+            .iter()
+            .map(|release| release.num.clone())
+            .collect()
+    }
+
     pub fn stable_releases(&self) -> Vec<&CrateReleaseInfo> {
         self.non_yanked_releases()
             .iter()
@@ -208,8 +352,8 @@ impl CrateInfo {
         current_versions.extend(non_yanked_releases.get(0).map(|r| &*r.num).iter());
         current_versions.extend(self.stable_releases().get(0).map(|r| &*r.num).iter());
 
-        // All releases in the last 30.5 days
-        for release in &non_yanked_releases {
+        // All releases in the last 30.5 days (release dates are not in the index)
+        for release in non_yanked_releases.iter().filter(|_| !self.from_index) {
             if esr_util::age_in_months(&esr_util::crate_to_iso8601(&release.created_at))? <= 1.0 {
                 current_versions.push(&*release.num);
             } else {
@@ -246,6 +390,32 @@ impl CrateInfo {
             .map(|s| s.as_str())
     }
 
+    pub fn get_homepage(&self) -> Option<&str> {
+        self
+            .general_info
+            .homepage
+            .as_ref()
+            .map(|s| s.as_str())
+    }
+
+    pub fn get_keywords(&self) -> &[String] {
+        self
+            .general_info
+            .keywords
+            .as_ref()
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn get_categories(&self) -> &[String] {
+        self
+            .general_info
+            .categories
+            .as_ref()
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
     pub fn get_license(&self) -> Option<&str> {
         // TODO: re-write when impl Try for Option is implemented
         let max_ver_rel = self.releases
@@ -266,6 +436,8 @@ impl CrateInfo {
     }
 }
 
+#[derive(Serialize, Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct CrateScoreInfo {
     // +ve
     has_desc: usize,
@@ -276,21 +448,35 @@ pub struct CrateScoreInfo {
     non_yanked_releases: usize,
     stable_releases: usize,
     last_2_non_yanked_releases_downloads: f64,
+    downloads_in_last_90_days: usize,
+    downloads_growth_pct: f64,
+    downloads_decline_pct: f64,
+    recent_downloads: usize,
     dependants: usize,
     hard_dependants: usize,
     dependants_on_current_versions: usize,
     dependants_from_non_owners: usize,
+    transitive_dependants: usize,
+    transitive_dependants_from_non_owners: usize,
     // -ve
     months_since_last_release: f64,
     empty_or_all_yanked: usize,
+    // Total downloads per day, oldest first
+    daily_downloads: Vec<usize>,
+    // Factors that couldn't be computed (e.g. offline)
+    unavailable: Vec<&'static str>,
 }
 
 impl CrateScoreInfo {
     async fn from_crate_info(crate_info: &CrateInfo) -> Result<Self> {
         let general_info = &crate_info.general_info;
 
-        let owners_info_fut = smol::spawn(CrateOwners::from_id_owned(general_info.id.clone()));
-        let dependants_info_fut = smol::spawn(Dependant::dependants_from_id(general_info.id.clone()));
+        let owners_info_fut = esr_retry::spawn(CrateOwners::from_id_owned(general_info.id.clone()));
+        let downloads_fut = esr_retry::spawn(CrateDownloads::from_id_owned(general_info.id.clone()));
+        let dependants_info_fut = esr_retry::spawn(dependants_from_id(general_info.id.clone()));
+        let transitive_dependants_info_fut = esr_retry::spawn(transitive_dependants_from_id(general_info.id.clone()));
+
+        let mut unavailable = Vec::new();
 
         let has_desc = general_info.description.is_some() as usize;
         let has_docs = general_info.documentation.is_some() as usize;
@@ -308,30 +494,73 @@ impl CrateScoreInfo {
             .sum();
 
         // time related info
-        let activity_span_in_months = esr_util::span_in_months(&esr_util::crate_to_iso8601(&general_info.created_at),
-                                                               &esr_util::crate_to_iso8601(&general_info.updated_at))?;
+        let (activity_span_in_months, months_since_last_release) = match crate_info.from_index {
+            true => {
+                unavailable.extend(&["has_desc", "has_docs", "has_license",
+                                     "activity_span_in_months",
+                                     "last_2_non_yanked_releases_downloads",
+                                     "months_since_last_release"]);
+                (0.0, 0.0)
+            },
+            false => {
+                let activity_span_in_months = esr_util::span_in_months(&esr_util::crate_to_iso8601(&general_info.created_at),
+                                                                       &esr_util::crate_to_iso8601(&general_info.updated_at))?;
+
+                let months_since_last_release = match crate_info.non_yanked_releases().get(0) {
+                    Some(last_release) => esr_util::age_in_months(&esr_util::crate_to_iso8601(&last_release.created_at))?,
+                    None => esr_util::age_in_months(&esr_util::crate_to_iso8601(&general_info.created_at))?,
+                };
+
+                (activity_span_in_months, months_since_last_release)
+            },
+        };
 
-        let months_since_last_release = match crate_info.non_yanked_releases().get(0) {
-            Some(last_release) => esr_util::age_in_months(&esr_util::crate_to_iso8601(&last_release.created_at))?,
-            None => esr_util::age_in_months(&esr_util::crate_to_iso8601(&general_info.created_at))?,
+        // Not all registries have download stats. So, failing here is not fatal.
+        let daily_downloads = match downloads_fut.await {
+            Ok(downloads) => downloads.daily_totals(chrono::Utc::now().naive_utc().date()),
+            Err(e) => {
+                log::debug!("Failed to get downloads of {}: {}", general_info.id, e);
+                unavailable.extend(&["downloads_in_last_90_days", "downloads_growth_pct", "downloads_decline_pct"]);
+                Vec::new()
+            },
+        };
+        let downloads_in_last_90_days = daily_downloads.iter().sum::<usize>();
+        // Split, so that both factors are non-negative
+        let downloads_change_pct = downloads_change_pct(&daily_downloads);
+        let downloads_growth_pct = downloads_change_pct.max(0.0);
+        let downloads_decline_pct = (-downloads_change_pct).max(0.0);
+
+        let recent_downloads = match general_info.recent_downloads {
+            Some(recent_downloads) => recent_downloads,
+            None => {
+                unavailable.push("recent_downloads");
+                0
+            },
         };
 
         let dependants_info = dependants_info_fut.await?;
-        let owners_info = owners_info_fut.await?;
+        let transitive_dependants_info = match transitive_dependants_info_fut.await? {
+            Some(transitive_dependants_info) => transitive_dependants_info,
+            None => {
+                unavailable.extend(&["transitive_dependants", "transitive_dependants_from_non_owners"]);
+                Vec::new()
+            },
+        };
 
         // Reverse dependencies
         let dependants = dependants_info.len();
+        let transitive_dependants = transitive_dependants_info.len();
 
         let current_versions = crate_info.get_current_versions()?;
         let hard_dependants = dependants_info
             .iter()
-            .filter(|dependant| dependant.default_features && !dependant.optional)
+            .filter(|dependant| dependant.has_default_features() && !dependant.is_optional())
             .count();
         let dependants_on_current_versions = dependants_info
             .iter()
             .filter(|dependant| {
                 current_versions.iter().any(|&ver| {
-                    match (Version::parse(ver), VersionReq::parse(&*dependant.req)) {
+                    match (Version::parse(ver), VersionReq::parse(dependant.get_req())) {
                         (Ok(ver), Ok(req)) => req.matches(&ver),
                         _ => false,
                     }
@@ -339,36 +568,39 @@ impl CrateScoreInfo {
             })
             .count();
 
-        // We do this in a separate step to make `with_threads()` work
-        let owners_ids: Vec<_> = owners_info
-            .users
-            .iter()
-            .map(|user| format!("user_id={}", user.id))
-            .collect();
-
-        let owners_crates = owners_ids
-            .into_iter()
-            .map(|id| smol::spawn(CrateSearch::from_id_owned(id)));
-
-        let owners_crates = future::join_all(owners_crates)
-            .await
-            .into_iter()
-            .map(|t| t.map_err(|e| e.into()))
-            .collect::<Result<Vec<_>>>()?;
-
-        let owners_crates_flat: Vec<_> = owners_crates.iter()
-            .flat_map(|search| search.crates.iter())
-            .collect();
-
-        let dependants_by_owners =
-            dependants_info
-                .iter()
-                .filter_map(|dependant| {
-                    owners_crates_flat.iter().find(|cr| cr.id == dependant.crate_name)
-                })
-                .count();
+        let owners_crates_res = match owners_info_fut.await {
+            Ok(owners_info) => Self::owners_crates(&owners_info).await,
+            Err(e) => Err(e),
+        };
 
-        let dependants_from_non_owners = dependants - dependants_by_owners;
+        let (dependants_from_non_owners, transitive_dependants_from_non_owners) = match owners_crates_res {
+            Ok(owners_crates) => {
+                let owners_crates_set: HashSet<_> = owners_crates.iter()
+                    .flat_map(|search| search.crates.iter())
+                    .map(|cr| &*cr.id)
+                    .collect();
+
+                let dependants_by_owners =
+                    dependants_info
+                        .iter()
+                        .filter(|dependant| owners_crates_set.contains(dependant.get_crate_name()))
+                        .count();
+
+                let transitive_dependants_by_owners =
+                    transitive_dependants_info
+                        .iter()
+                        .filter(|dependant| owners_crates_set.contains(&***dependant))
+                        .count();
+
+                (dependants - dependants_by_owners, transitive_dependants - transitive_dependants_by_owners)
+            },
+            // Owners are not in the index
+            Err(EsrError::Offline(_)) => {
+                unavailable.extend(&["dependants_from_non_owners", "transitive_dependants_from_non_owners"]);
+                (0, 0)
+            },
+            Err(e) => Err(e)?,
+        };
 
         Ok(Self {
             // +ve
@@ -380,50 +612,75 @@ impl CrateScoreInfo {
             non_yanked_releases,
             stable_releases,
             last_2_non_yanked_releases_downloads,
+            downloads_in_last_90_days,
+            downloads_growth_pct,
+            downloads_decline_pct,
+            recent_downloads,
             dependants,
             hard_dependants,
             dependants_on_current_versions,
             dependants_from_non_owners,
+            transitive_dependants,
+            transitive_dependants_from_non_owners,
             // -ve
             months_since_last_release,
             empty_or_all_yanked,
+            daily_downloads,
+            unavailable,
         })
     }
 
-    fn mk_score(&self) -> (Vec<(String, String, String)>, f64, f64) {
+    async fn owners_crates(owners_info: &CrateOwners) -> Result<Vec<CrateSearch>> {
+        // We do this in a separate step to make `with_threads()` work
+        let owners_ids: Vec<_> = owners_info
+            .users
+            .iter()
+            .map(|user| format!("user_id={}", user.id))
+            .collect();
+
+        let owners_crates = owners_ids
+            .into_iter()
+            .map(|id| esr_retry::spawn(CrateSearch::from_id_owned(id)));
+
+        future::join_all(owners_crates)
+            .await
+            .into_iter()
+            .map(|t| t.map_err(|e| e.into()))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    fn mk_score(&self, weights: &Weights) -> (Vec<ScoreRow>, f64, f64) {
         let mut positive_score = 0.0;
         let mut negative_score = 0.0;
         let mut table = Vec::with_capacity(100);
+        let factors = weights.crate_factors();
+        let unavailable = &self.unavailable;
 
         // +ve
-        score_add!(table, positive_score, self.has_desc, 5.0);
-        score_add!(table, positive_score, self.has_license, 5.0);
-        score_add!(table, positive_score, self.has_docs, 15.0);
-
-        score_add!(table,
-                   positive_score,
-                   self.activity_span_in_months.powf(0.5),
-                   6.0);
-
-        score_add!(table, positive_score, self.releases, 0.5);
-        score_add!(table, positive_score, self.non_yanked_releases, 0.5);
-        score_add!(table, positive_score, self.stable_releases, 0.5);
-        score_add!(table,
-                   positive_score,
-                   self.last_2_non_yanked_releases_downloads.powf(0.5),
-                   0.1);
-
-        score_add!(table, positive_score, self.dependants, 0.5);
-        score_add!(table, positive_score, self.hard_dependants, 0.75);
-        score_add!(table, positive_score, self.dependants_on_current_versions, 0.75);
-        score_add!(table, positive_score, self.dependants_from_non_owners, 2.5);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.has_desc);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.has_license);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.has_docs);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.activity_span_in_months);
+
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.releases);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.non_yanked_releases);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.stable_releases);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.last_2_non_yanked_releases_downloads);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.downloads_in_last_90_days);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.downloads_growth_pct);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.downloads_decline_pct);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.recent_downloads);
+
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.dependants);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.hard_dependants);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.dependants_on_current_versions);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.dependants_from_non_owners);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.transitive_dependants);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.transitive_dependants_from_non_owners);
 
         // -ve
-        score_add!(table,
-                   negative_score,
-                   self.months_since_last_release.powf(1.5),
-                   -2.0);
-        score_add!(table, negative_score, self.empty_or_all_yanked, -5000.0);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.months_since_last_release);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.empty_or_all_yanked);
 
         (table, positive_score, negative_score)
     }
@@ -436,6 +693,34 @@ impl CrateScoreInfo {
         self.dependants_from_non_owners
     }
 
+    pub fn get_transitive_dependants(&self) -> usize {
+        self.transitive_dependants
+    }
+
+    pub fn get_transitive_dependants_from_non_owners(&self) -> usize {
+        self.transitive_dependants_from_non_owners
+    }
+
+    pub fn get_downloads_in_last_90_days(&self) -> usize {
+        self.downloads_in_last_90_days
+    }
+
+    pub fn get_downloads_growth_pct(&self) -> f64 {
+        self.downloads_growth_pct
+    }
+
+    pub fn get_downloads_decline_pct(&self) -> f64 {
+        self.downloads_decline_pct
+    }
+
+    pub fn get_recent_downloads(&self) -> usize {
+        self.recent_downloads
+    }
+
+    pub fn get_daily_downloads(&self) -> &[usize] {
+        &self.daily_downloads
+    }
+
     pub fn get_releases(&self) -> usize {
         self.releases
     }
@@ -451,26 +736,39 @@ impl CrateScoreInfo {
     pub fn get_months_since_last_release(&self) -> f64 {
         self.months_since_last_release
     }
+
+    pub fn is_available(&self, factor: &str) -> bool {
+        !self.unavailable.contains(&factor)
+    }
 }
 
 // ==============
 
+#[derive(Serialize, Debug)]
 pub struct CrateInfoWithScore {
+    #[serde(rename = "info")]
     crate_info: CrateInfo,
+    #[serde(rename = "factors")]
     crate_score_info: CrateScoreInfo,
+    weights_profile: String,
+    #[serde(rename = "positive")]
     score_positive: f64,
+    #[serde(rename = "negative")]
     score_negative: f64,
-    score_table: Vec<(String, String, String)>,
+    #[serde(rename = "table")]
+    score_table: Vec<ScoreRow>,
 }
 
 impl CrateInfoWithScore {
     pub async fn from_info(crate_info: CrateInfo) -> Result<Self> {
         let crate_score_info = CrateScoreInfo::from_crate_info(&crate_info).await?;
-        let (score_table, score_positive, score_negative) = crate_score_info.mk_score();
+        let weights = esr_weights::get_weights();
+        let (score_table, score_positive, score_negative) = crate_score_info.mk_score(weights);
 
         Ok(Self {
             crate_info,
             crate_score_info,
+            weights_profile: weights.get_name().into(),
             score_positive,
             score_negative,
             score_table,
@@ -482,6 +780,31 @@ impl CrateInfoWithScore {
         Self::from_info(crate_info).await
     }
 
+    // Scored without fetching anything. Factors not from `crate_info` are 0.
+    #[cfg(test)]
+    pub(crate) fn from_info_without_stats(crate_info: CrateInfo,
+                                          months_since_last_release: f64,
+                                          unavailable: Vec<&'static str>) -> Self {
+        let crate_score_info = CrateScoreInfo {
+            has_license: crate_info.get_license().is_some() as usize,
+            empty_or_all_yanked: crate_info.empty_or_all_yanked() as usize,
+            months_since_last_release,
+            unavailable,
+            ..CrateScoreInfo::default()
+        };
+        let weights = Weights::default();
+        let (score_table, score_positive, score_negative) = crate_score_info.mk_score(&weights);
+
+        Self {
+            crate_info,
+            crate_score_info,
+            weights_profile: weights.get_name().into(),
+            score_positive,
+            score_negative,
+            score_table,
+        }
+    }
+
     pub fn get_info(&self) -> &CrateInfo {
         &self.crate_info
     }
@@ -490,11 +813,15 @@ impl CrateInfoWithScore {
         &self.crate_score_info
     }
 
+    pub fn get_weights_profile(&self) -> &str {
+        &self.weights_profile
+    }
+
     pub fn get_score_tuple(&self) -> (f64, f64) {
         (self.score_positive, self.score_negative)
     }
 
-    pub fn get_score_table(&self) -> &[(String, String, String)] {
+    pub fn get_score_table(&self) -> &[ScoreRow] {
         &self.score_table
     }
 }
@@ -527,7 +854,7 @@ impl EsrFromMulti for CrateSearch {
 impl EsrFrom for CrateSearch {
     // id here is all search params
     fn url_from_id(id: &str) -> String {
-        String::from("https://crates.io/api/v1/crates?per_page=100&") + id
+        esr_registry::get_registry().api_url("crates?per_page=100&") + id
     }
 
     async fn from_id(id: &str) -> Result<Self> {
@@ -538,12 +865,250 @@ impl EsrFrom for CrateSearch {
 impl CrateSearch {
     // id here is all search params
     pub async fn from_id_single_page(id: &str) -> Result<Self> {
-        let mut url = String::from("https://crates.io/api/v1/crates?");
+        let mut url = esr_registry::get_registry().api_url("crates?");
         url.push_str(id);
         EsrFromMulti::from_url_multi(&*url, false).await
     }
 
+    // Search params narrowing results to `category` (a slug) and all `keywords`.
+    // crates.io only takes a single `keyword`, `all_keywords` is for more.
+    pub fn filter_params(category: Option<&str>, keywords: &[&str]) -> String {
+        let mut params = String::with_capacity(64);
+
+        if let Some(category) = category {
+            params += "&category=";
+            params += &*esr_util::percent_encode(category);
+        }
+
+        match keywords {
+            [] => (),
+            [keyword] => {
+                params += "&keyword=";
+                params += &*esr_util::percent_encode(keyword);
+            },
+            keywords => {
+                // Space-separated, with spaces as '+'
+                let keywords: Vec<_> = keywords.iter().map(|kw| esr_util::percent_encode(kw)).collect();
+                params += "&all_keywords=";
+                params += &*keywords.join("+");
+            },
+        }
+
+        params
+    }
+
     pub fn get_crates(&self) -> &[CrateGeneralInfo] {
         &self.crates
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Category {
+    slug: String,
+    category: String,
+    description: String,
+    crates_cnt: usize,
+}
+
+impl Category {
+    pub fn get_slug(&self) -> &str {
+        &self.slug
+    }
+
+    pub fn get_category(&self) -> &str {
+        &self.category
+    }
+
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn get_crates_cnt(&self) -> usize {
+        self.crates_cnt
+    }
+}
+
+// Top-level categories of the registry
+#[derive(Deserialize, Debug, Clone)]
+pub struct CrateCategories {
+    categories: Vec<Category>,
+    meta: Meta,
+}
+
+impl EsrFromMulti for CrateCategories {
+    type Inner = Category;
+
+    fn get_meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    fn get_inner(&self) -> &Vec<Self::Inner> {
+        &self.categories
+    }
+
+    fn get_inner_mut(&mut self) -> &mut Vec<Self::Inner> {
+        &mut self.categories
+    }
+}
+
+impl EsrFrom for CrateCategories {}
+
+impl CrateCategories {
+    pub async fn from_registry() -> Result<Self> {
+        let url = esr_registry::get_registry().api_url("categories?per_page=100");
+        EsrFromMulti::from_url_multi(&*url, true).await
+    }
+
+    pub fn get_categories(&self) -> &[Category] {
+        &self.categories
+    }
+}
+
+// A crates.io user (e.g. "dtolnay"), or team (e.g. "github:serde-rs:publish")
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Owner {
+    id: usize,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserOwner {
+    user: Owner,
+}
+
+impl EsrFrom for UserOwner {
+    fn url_from_id(id: &str) -> String {
+        esr_registry::get_registry().api_url("users/") + id
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TeamOwner {
+    team: Owner,
+}
+
+impl EsrFrom for TeamOwner {
+    fn url_from_id(id: &str) -> String {
+        esr_registry::get_registry().api_url("teams/") + id
+    }
+}
+
+impl Owner {
+    pub async fn from_login(login: &str) -> Result<Self> {
+        match login.contains(':') {
+            true => Ok(TeamOwner::from_id(login).await?.team),
+            false => Ok(UserOwner::from_id(login).await?.user),
+        }
+    }
+
+    // Team logins are "provider:org:team"
+    pub fn is_team(&self) -> bool {
+        self.login.contains(':')
+    }
+
+    pub fn get_login(&self) -> &str {
+        &self.login
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_ref().map(|s| s.as_str())
+    }
+
+    // All crates owned by this user or team
+    pub async fn crates(&self) -> Result<CrateSearch> {
+        let param = match self.is_team() {
+            true => "team_id",
+            false => "user_id",
+        };
+        CrateSearch::from_id(&format!("{}={}", param, self.id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downloads(days: &[(&str, usize)]) -> CrateDownloads {
+        let version_downloads = days.iter()
+            .map(|&(date, downloads)| DailyDownloads { date: date.into(), downloads })
+            .collect();
+        CrateDownloads { version_downloads, meta: CrateDownloadsMeta::default() }
+    }
+
+    #[test]
+    fn daily_totals_end_today() {
+        let today = NaiveDate::from_ymd_opt(2021, 3, 31).unwrap();
+        // Versions are summed, quiet days are 0, and days outside the window are dropped
+        let dl = downloads(&[("2020-12-31", 7), ("2021-01-01", 5), ("2021-01-01", 1), ("2021-03-01", 9), ("2021-03-30", 3)]);
+
+        let daily = dl.daily_totals(today);
+        assert_eq!(daily.len(), 90);
+        assert_eq!(daily[0], 6);
+        assert_eq!(daily[59], 9);
+        assert_eq!(daily[88], 3);
+        assert_eq!(daily[89], 0);
+        assert_eq!(daily.iter().sum::<usize>(), 18);
+
+        // Stale stats of a crate nobody downloads anymore
+        let daily = downloads(&[("2020-06-01", 100)]).daily_totals(today);
+        assert_eq!(daily, vec![0; 90]);
+    }
+
+    #[test]
+    fn downloads_change_between_minus_and_plus_100() {
+        let daily = |prev: usize, last: usize| [vec![0; 30], vec![prev; 30], vec![last; 30]].concat();
+
+        assert_eq!(downloads_change_pct(&daily(0, 0)), 0.0);
+        assert_eq!(downloads_change_pct(&daily(0, 5)), 100.0);
+        assert_eq!(downloads_change_pct(&daily(4, 5)), 25.0);
+        assert_eq!(downloads_change_pct(&daily(4, 40)), 100.0);
+        assert_eq!(downloads_change_pct(&daily(4, 1)), -75.0);
+        assert_eq!(downloads_change_pct(&daily(4, 0)), -100.0);
+    }
+
+    #[test]
+    fn filter_params_are_encoded() {
+        assert_eq!(CrateSearch::filter_params(None, &[]), "");
+        assert_eq!(CrateSearch::filter_params(Some("command-line-utilities"), &["cli"]),
+                   "&category=command-line-utilities&keyword=cli");
+        assert_eq!(CrateSearch::filter_params(Some("a&b=c"), &["c++"]), "&category=a%26b%3Dc&keyword=c%2B%2B");
+        assert_eq!(CrateSearch::filter_params(None, &["no-std", "x y", "über"]),
+                   "&all_keywords=no-std+x%20y+%C3%BCber");
+    }
+
+    #[test]
+    fn default_weights_reproduce_baseline_score() {
+        // Factor values of mio from the README. Factors added later are set,
+        // but have no weight in the default profile.
+        let info = CrateScoreInfo {
+            has_desc: 1,
+            has_docs: 1,
+            has_license: 1,
+            activity_span_in_months: 6.306f64.powi(2),
+            releases: 32,
+            non_yanked_releases: 32,
+            stable_releases: 32,
+            last_2_non_yanked_releases_downloads: 525.966f64.powi(2),
+            downloads_in_last_90_days: 1_500_000,
+            downloads_growth_pct: 12.5,
+            downloads_decline_pct: 3.0,
+            recent_downloads: 1_500_000,
+            dependants: 143,
+            hard_dependants: 136,
+            dependants_on_current_versions: 116,
+            dependants_from_non_owners: 127,
+            transitive_dependants: 4000,
+            transitive_dependants_from_non_owners: 3900,
+            months_since_last_release: 5.240f64.powf(1.0 / 1.5),
+            empty_or_all_yanked: 0,
+            daily_downloads: Vec::new(),
+            unavailable: Vec::new(),
+        };
+
+        let (table, pos, neg) = info.mk_score(&Weights::default());
+        assert!((pos - 741.433).abs() < 1e-2, "{}", pos);
+        assert!((neg - -10.480).abs() < 1e-2, "{}", neg);
+        assert_eq!(table.iter().filter(|row| row.weight != 0.0).count(), 14);
+    }
+}
@@ -0,0 +1,572 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::esr_errors::{Result, EsrError};
+
+const DEP_SECTIONS: &[(&str, DepKind)] = &[
+    ("dependencies", DepKind::Normal),
+    ("build-dependencies", DepKind::Build),
+    ("build_dependencies", DepKind::Build),
+    ("dev-dependencies", DepKind::Dev),
+    ("dev_dependencies", DepKind::Dev),
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum DepKind {
+    Normal,
+    Build,
+    Dev,
+    Transitive,
+}
+
+impl DepKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DepKind::Normal => "normal",
+            DepKind::Build => "build",
+            DepKind::Dev => "dev",
+            DepKind::Transitive => "transitive",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LocalDep {
+    name: String, // crates.io name, not the renamed one!
+    kinds: Vec<DepKind>,
+    reqs: Vec<String>,
+    locked_version: Option<String>,
+}
+
+impl LocalDep {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_kinds(&self) -> &[DepKind] {
+        &self.kinds
+    }
+
+    pub fn get_reqs(&self) -> &[String] {
+        &self.reqs
+    }
+
+    pub fn get_locked_version(&self) -> Option<&str> {
+        self.locked_version.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn kinds_str(&self) -> String {
+        self.kinds
+            .iter()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    // "name", "name version" or "name version (source)"
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+impl LockedPackage {
+    // Only packages from a registry (git or sparse index) can be scored
+    fn is_from_registry(&self) -> bool {
+        self.source.as_ref().map(|s| s.starts_with("registry+") || s.starts_with("sparse+")).unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CargoLock {
+    #[serde(rename = "package", default)]
+    packages: Vec<LockedPackage>,
+}
+
+impl CargoLock {
+    fn from_path(path: &Path) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn registry_packages(&self) -> impl Iterator<Item=&LockedPackage> {
+        self.packages
+            .iter()
+            .filter(|p| p.is_from_registry())
+    }
+
+    // Registry packages in the dependency graph of the local packages named in `roots`
+    fn registry_packages_from(&self, roots: &[String]) -> Vec<&LockedPackage> {
+        let mut seen: HashSet<usize> = self.packages
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.source.is_none() && roots.contains(&p.name))
+            .map(|(idx, _)| idx)
+            .collect();
+        let mut queue: Vec<usize> = seen.iter().cloned().collect();
+
+        while let Some(idx) = queue.pop() {
+            for dep in &self.packages[idx].dependencies {
+                for dep_idx in self.resolve(dep) {
+                    if seen.insert(dep_idx) {
+                        queue.push(dep_idx);
+                    }
+                }
+            }
+        }
+
+        self.packages
+            .iter()
+            .enumerate()
+            .filter(|(idx, p)| p.is_from_registry() && seen.contains(idx))
+            .map(|(_, p)| p)
+            .collect()
+    }
+
+    // Indices of the packages a dependency entry refers to.
+    // Version and source are only given if the name is ambiguous.
+    fn resolve<'a>(&'a self, dep: &'a str) -> impl Iterator<Item=usize> + 'a {
+        let mut parts = dep.splitn(3, ' ');
+        let name = parts.next().unwrap_or_default();
+        let version = parts.next();
+        let source = parts.next().map(|s| s.trim_start_matches('(').trim_end_matches(')'));
+
+        self.packages
+            .iter()
+            .enumerate()
+            .filter(move |(_, p)| p.name == name
+                    && version.map(|v| v == p.version).unwrap_or(true)
+                    && source.map(|s| p.source.as_deref() == Some(s)).unwrap_or(true))
+            .map(|(idx, _)| idx)
+    }
+
+    fn locked_version(&self, name: &str, reqs: &[String]) -> Option<String> {
+        let candidates: Vec<_> = self.registry_packages()
+            .filter(|p| p.name == name)
+            .collect();
+
+        let matches_reqs = |p: &&&LockedPackage| {
+            reqs.iter().all(|req| match (VersionReq::parse(req), Version::parse(&p.version)) {
+                (Ok(req), Ok(ver)) => req.matches(&ver),
+                _ => true,
+            })
+        };
+
+        candidates
+            .iter()
+            .find(matches_reqs)
+            .or_else(|| candidates.get(0))
+            .map(|p| p.version.clone())
+    }
+}
+
+pub struct LocalDeps {
+    deps: Vec<LocalDep>,
+    lock_path: Option<PathBuf>,
+}
+
+impl LocalDeps {
+    // Read direct dependencies from the manifest at `manifest_path`, and all
+    // workspace members if it's a workspace root. Locked versions are read from
+    // Cargo.lock if found. Transitive dependencies require Cargo.lock, and are
+    // only those of the selected packages, not of the whole workspace.
+    pub fn from_manifest_path(manifest_path: &Path, transitive: bool) -> Result<Self> {
+        // Absolute path, so we can look for Cargo.lock and workspace roots in parent dirs
+        let manifest_path = &manifest_path.canonicalize()
+            .map_err(|e| EsrError::Other(format!("Failed to find {}: {}", manifest_path.display(), e)))?;
+        let manifest = read_manifest(manifest_path)?;
+        let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+        let ws_root = find_workspace_root(manifest_dir, &manifest)?;
+
+        let mut manifests = vec![manifest_path.to_path_buf()];
+        if let Some((ref ws_dir, ref ws_manifest)) = ws_root {
+            if ws_dir == manifest_dir {
+                manifests.extend(workspace_members(ws_dir, ws_manifest)?);
+            }
+        }
+
+        let ws_deps = ws_root
+            .as_ref()
+            .and_then(|(_, ws_manifest)| ws_manifest.get("workspace"))
+            .and_then(|ws| ws.get("dependencies"))
+            .and_then(|deps| deps.as_table())
+            .cloned()
+            .unwrap_or_default();
+
+        // name => (kinds, reqs)
+        let mut direct = BTreeMap::new();
+        let mut packages = Vec::with_capacity(manifests.len());
+        for path in &manifests {
+            let manifest = read_manifest(path)?;
+            collect_manifest_deps(&manifest, &ws_deps, &mut direct);
+
+            // None for virtual workspace roots
+            if let Some(name) = manifest.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()) {
+                packages.push(name.to_string());
+            }
+        }
+
+        let lock_path = manifest_dir
+            .ancestors()
+            .map(|dir| dir.join("Cargo.lock"))
+            .find(|lock| lock.is_file());
+
+        let lock = match lock_path {
+            Some(ref lock_path) => Some(CargoLock::from_path(lock_path)?),
+            None if transitive => Err("Transitive dependencies requested but no Cargo.lock was found")?,
+            None => None,
+        };
+
+        let mut deps: Vec<_> = direct
+            .into_iter()
+            .map(|(name, (mut kinds, reqs)): (String, (Vec<DepKind>, Vec<String>))| {
+                kinds.sort();
+                kinds.dedup();
+                let locked_version = lock.as_ref().and_then(|lock| lock.locked_version(&name, &reqs));
+                LocalDep { name, kinds, reqs, locked_version }
+            })
+            .collect();
+
+        if let (true, Some(ref lock)) = (transitive, &lock) {
+            let mut transitive_deps: Vec<_> = lock.registry_packages_from(&packages)
+                .into_iter()
+                .filter(|p| deps.iter().all(|dep| dep.name != p.name))
+                .map(|p| LocalDep {
+                    name: p.name.clone(),
+                    kinds: vec![DepKind::Transitive],
+                    reqs: Vec::new(),
+                    locked_version: Some(p.version.clone()),
+                })
+                .collect();
+
+            // Multiple versions of the same crate are scored once
+            transitive_deps.dedup_by(|a, b| a.name == b.name);
+            deps.extend(transitive_deps);
+        }
+
+        Ok(Self { deps, lock_path })
+    }
+
+    pub fn get_deps(&self) -> &[LocalDep] {
+        &self.deps
+    }
+
+    pub fn get_lock_path(&self) -> Option<&Path> {
+        self.lock_path.as_ref().map(|p| p.as_path())
+    }
+
+    pub fn get_ids(&self) -> Vec<String> {
+        self.deps.iter().map(|dep| dep.name.clone()).collect()
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Value> {
+    let manifest_str = fs::read_to_string(path)
+        .map_err(|e| EsrError::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+    Ok(toml::from_str(&manifest_str)?)
+}
+
+fn find_workspace_root(manifest_dir: &Path, manifest: &Value) -> Result<Option<(PathBuf, Value)>> {
+    if manifest.get("workspace").is_some() {
+        return Ok(Some((manifest_dir.to_path_buf(), manifest.clone())));
+    }
+
+    for dir in manifest_dir.ancestors().skip(1) {
+        let candidate = dir.join("Cargo.toml");
+        if candidate.is_file() {
+            let candidate_manifest = read_manifest(&candidate)?;
+            if candidate_manifest.get("workspace").is_some() {
+                return Ok(Some((dir.to_path_buf(), candidate_manifest)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn workspace_members(ws_dir: &Path, ws_manifest: &Value) -> Result<Vec<PathBuf>> {
+    let members = ws_manifest
+        .get("workspace")
+        .and_then(|ws| ws.get("members"))
+        .and_then(|members| members.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut ret = Vec::with_capacity(members.len());
+    for member in members.iter().filter_map(|m| m.as_str()) {
+        // Only trailing globs (e.g. "crates/*") are supported
+        if member.ends_with("/*") {
+            let parent = ws_dir.join(member.trim_end_matches("/*"));
+            for entry in fs::read_dir(parent)? {
+                let manifest = entry?.path().join("Cargo.toml");
+                if manifest.is_file() {
+                    ret.push(manifest);
+                }
+            }
+        } else {
+            let manifest = ws_dir.join(member).join("Cargo.toml");
+            if manifest.is_file() {
+                ret.push(manifest);
+            }
+        }
+    }
+
+    ret.sort();
+    Ok(ret)
+}
+
+fn collect_manifest_deps(manifest: &Value,
+                         ws_deps: &toml::value::Table,
+                         direct: &mut BTreeMap<String, (Vec<DepKind>, Vec<String>)>) {
+    let targets = manifest
+        .get("target")
+        .and_then(|t| t.as_table())
+        .map(|t| t.values().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    // Top-level dependency sections + platform-specific ones
+    for section_parent in Some(manifest).into_iter().chain(targets) {
+        for &(section, kind) in DEP_SECTIONS {
+            let deps = match section_parent.get(section).and_then(|s| s.as_table()) {
+                Some(deps) => deps,
+                None => continue,
+            };
+
+            for (dep_name, dep) in deps {
+                // Inherited from the workspace
+                let dep = match dep.get("workspace").and_then(|w| w.as_bool()) {
+                    Some(true) => match ws_deps.get(dep_name) {
+                        Some(ws_dep) => ws_dep,
+                        None => continue,
+                    },
+                    _ => dep,
+                };
+
+                if let Some((name, req)) = registry_dep(dep_name, dep) {
+                    let entry = direct.entry(name).or_insert_with(|| (Vec::new(), Vec::new()));
+                    entry.0.push(kind);
+                    entry.1.extend(req);
+                }
+            }
+        }
+    }
+}
+
+// Returns the crate name and the version requirement, if any.
+// None is returned for path and git dependencies.
+fn registry_dep(dep_name: &str, dep: &Value) -> Option<(String, Option<String>)> {
+    match *dep {
+        Value::String(ref req) => Some((dep_name.into(), Some(req.clone()))),
+        Value::Table(ref t) if t.contains_key("path") || t.contains_key("git") => None,
+        Value::Table(ref t) => {
+            let name = t.get("package").and_then(|p| p.as_str()).unwrap_or(dep_name);
+            let req = t.get("version").and_then(|v| v.as_str()).map(String::from);
+            Some((name.into(), req))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_packages_from_git_and_sparse_indexes() {
+        let lock: CargoLock = toml::from_str(r#"
+            [[package]]
+            name = "app"
+            version = "0.1.0"
+
+            [[package]]
+            name = "serde"
+            version = "1.0.100"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "internal"
+            version = "2.1.0"
+            source = "sparse+https://corp.example/index/"
+
+            [[package]]
+            name = "patched"
+            version = "0.3.0"
+            source = "git+https://example.com/patched.git#0123456789abcdef"
+        "#).unwrap();
+
+        let names: Vec<_> = lock.registry_packages().map(|p| &*p.name).collect();
+        assert_eq!(names, ["serde", "internal"]);
+        assert_eq!(lock.locked_version("internal", &["^2".into()]).as_deref(), Some("2.1.0"));
+        assert_eq!(lock.locked_version("patched", &[]), None);
+    }
+
+    fn write_workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("esr-deps-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let files = [
+            ("Cargo.toml", r#"
+                [workspace]
+                members = ["crates/*"]
+
+                [workspace.dependencies]
+                serde = "1.0"
+                log = { version = "0.4", default-features = false }
+            "#),
+            ("crates/app/Cargo.toml", r#"
+                [package]
+                name = "app"
+                version = "0.1.0"
+
+                [dependencies]
+                serde = { workspace = true }
+                util = { path = "../util" }
+                json = { package = "serde_json", version = "1" }
+                missing = { workspace = true }
+
+                [dev-dependencies]
+                log = { workspace = true }
+
+                [target.'cfg(unix)'.dependencies]
+                libc = "0.2"
+            "#),
+            ("crates/util/Cargo.toml", r#"
+                [package]
+                name = "util"
+                version = "0.1.0"
+
+                [dependencies]
+                itoa = "1"
+                patched = { git = "https://example.com/patched.git" }
+            "#),
+            ("crates/tool/Cargo.toml", r#"
+                [package]
+                name = "tool"
+                version = "0.1.0"
+
+                [build-dependencies]
+                cc = "1"
+            "#),
+            ("Cargo.lock", r#"
+                [[package]]
+                name = "app"
+                version = "0.1.0"
+                dependencies = ["libc", "log", "serde", "serde_json", "util"]
+
+                [[package]]
+                name = "cc"
+                version = "1.0.70"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "itoa"
+                version = "0.4.8"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "itoa"
+                version = "1.0.1"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "libc"
+                version = "0.2.100"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "log"
+                version = "0.4.14"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "patched"
+                version = "0.3.0"
+                source = "git+https://example.com/patched.git#0123456789abcdef"
+                dependencies = ["ryu"]
+
+                [[package]]
+                name = "ryu"
+                version = "1.0.5"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "serde"
+                version = "1.0.100"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+
+                [[package]]
+                name = "serde_json"
+                version = "1.0.50"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+                dependencies = ["itoa 0.4.8 (registry+https://github.com/rust-lang/crates.io-index)", "serde"]
+
+                [[package]]
+                name = "tool"
+                version = "0.1.0"
+                dependencies = ["cc"]
+
+                [[package]]
+                name = "util"
+                version = "0.1.0"
+                dependencies = ["itoa 1.0.1", "patched"]
+            "#),
+        ];
+
+        for (path, contents) in &files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn workspace_members_deps() {
+        let dir = write_workspace("members");
+        let local_deps = LocalDeps::from_manifest_path(&dir.join("Cargo.toml"), false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Path, git and missing workspace deps are skipped. Renamed deps use the package name.
+        let deps = local_deps.get_deps();
+        assert_eq!(local_deps.get_ids(), ["cc", "itoa", "libc", "log", "serde", "serde_json"]);
+        assert_eq!(deps[0].get_kinds(), [DepKind::Build]);
+        assert_eq!(deps[3].get_kinds(), [DepKind::Dev]);
+        assert_eq!(deps[3].get_reqs(), ["0.4"]);
+        assert_eq!(deps[4].get_reqs(), ["1.0"]);
+        assert_eq!(deps[5].get_reqs(), ["1"]);
+        assert_eq!(deps[5].get_locked_version(), Some("1.0.50"));
+        // The version required by util, not the one serde_json depends on
+        assert_eq!(deps[1].get_locked_version(), Some("1.0.1"));
+    }
+
+    #[test]
+    fn transitive_deps_of_selected_member() {
+        let dir = write_workspace("transitive");
+        let local_deps = LocalDeps::from_manifest_path(&dir.join("crates/app/Cargo.toml"), true).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Through path and git deps, but not from other members (cc of tool)
+        let kinds: Vec<_> = local_deps.get_deps().iter().map(|dep| (dep.get_name(), dep.kinds_str())).collect();
+        assert_eq!(kinds, [("libc", "normal".into()), ("log", "dev".into()), ("serde", "normal".into()),
+                           ("serde_json", "normal".into()), ("itoa", "transitive".into()), ("ryu", "transitive".into())]);
+    }
+}
@@ -19,8 +19,21 @@ pub enum EsrError {
     TimeParse(time::ParseError),
     SerdeJson(serde_json::Error),
     Regex(regex::Error),
+    Toml(toml::de::Error),
     Isahc(isahc::Error),
+    Sqlite(rusqlite::Error),
     CratesIndex(String),
+    Offline(String),
+    // HTTP errors. URL (without tokens), and the API's error message if any.
+    NotFound(String, Option<String>),
+    // 401, or 403 not caused by a rate limit
+    Unauthorized(String, Option<String>),
+    // Host, and seconds until the rate limit is reset
+    RateLimited(String, u64),
+    // Any other 4xx status (e.g. 400, 410, 422, or 429 with no rate limit info)
+    ClientError(u16, String, Option<String>),
+    // 5xx status
+    ServerError(u16, String, Option<String>),
     Other(String),
 }
 
@@ -31,13 +44,52 @@ impl fmt::Display for EsrError {
             EsrError::TimeParse(ref e) => write!(f, "Time parsing Error: {}", e),
             EsrError::SerdeJson(ref e) => write!(f, "Deserialization Error: {}", e),
             EsrError::Regex(ref e) => write!(f, "Regex Error: {}", e),
+            EsrError::Toml(ref e) => write!(f, "TOML Error: {}", e),
             EsrError::Isahc(ref e) => write!(f, "isahc Error: {}", e),
+            EsrError::Sqlite(ref e) => write!(f, "SQLite Error: {}", e),
             EsrError::CratesIndex(ref e) => write!(f, "CratesIndex Error: {}", e),
+            EsrError::Offline(ref e) => write!(f, "Offline: no cached data for '{}'", e),
+            EsrError::NotFound(ref url, ref msg) => {
+                write!(f, "Not found: '{}'", url)?;
+                write_api_msg(f, msg)
+            },
+            EsrError::Unauthorized(ref url, ref msg) => {
+                write!(f, "Unauthorized: '{}'", url)?;
+                write_api_msg(f, msg)
+            },
+            EsrError::ClientError(status, ref url, ref msg) => {
+                write!(f, "HTTP Client Error {}: '{}'", status, url)?;
+                write_api_msg(f, msg)
+            },
+            EsrError::ServerError(status, ref url, ref msg) => {
+                write!(f, "HTTP Server Error {}: '{}'", status, url)?;
+                write_api_msg(f, msg)
+            },
+            EsrError::RateLimited(ref host, secs) => {
+                write!(f, "Rate limited by '{}' (resets in {} minutes)", host, (secs + 59) / 60)
+            },
             EsrError::Other(ref e) => write!(f, "Error: {}", e),
         }
     }
 }
 
+fn write_api_msg(f: &mut fmt::Formatter, msg: &Option<String>) -> fmt::Result {
+    match *msg {
+        Some(ref msg) => write!(f, ": {}", msg),
+        None => Ok(()),
+    }
+}
+
+impl EsrError {
+    // Is this a 404? e.g. a crate or repo that does not exist.
+    pub fn is_not_found(&self) -> bool {
+        match *self {
+            EsrError::NotFound(..) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<::std::io::Error> for EsrError {
     fn from(e: ::std::io::Error) -> Self {
         EsrError::StdIO(e)
@@ -62,12 +114,24 @@ impl From<regex::Error> for EsrError {
     }
 }
 
+impl From<toml::de::Error> for EsrError {
+    fn from(e: toml::de::Error) -> Self {
+        EsrError::Toml(e)
+    }
+}
+
 impl From<isahc::Error> for EsrError {
     fn from(e: isahc::Error) -> Self {
         EsrError::Isahc(e)
     }
 }
 
+impl From<rusqlite::Error> for EsrError {
+    fn from(e: rusqlite::Error) -> Self {
+        EsrError::Sqlite(e)
+    }
+}
+
 impl From<crates_index::Error> for EsrError {
     fn from(e: crates_index::Error) -> Self {
         EsrError::CratesIndex(e.to_string())
@@ -11,12 +11,20 @@
 
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, de::DeserializeOwned};
-use isahc::{HttpClientBuilder, HttpClient, AsyncReadResponseExt};
+use isahc::{HttpClientBuilder, HttpClient, Request, Response, AsyncBody, AsyncReadResponseExt};
+use isahc::http::StatusCode;
 use isahc::config::{Configurable, RedirectPolicy};
 use async_trait::async_trait;
 use futures::future;
 
-use crate::esr_errors::Result;
+use std::time::Duration;
+
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_cache;
+use crate::esr_registry;
+use crate::esr_ratelimit;
+use crate::esr_retry;
+use crate::esr_config;
 
 fn get_static_client() -> Result<&'static HttpClient> {
     static RET: OnceCell<HttpClient> = OnceCell::new();
@@ -32,6 +40,194 @@ fn get_static_client() -> Result<&'static HttpClient> {
 
 }
 
+// A JSON body POSTed instead of a GET (e.g. a GraphQL query)
+#[derive(Clone, Copy)]
+pub(crate) struct JsonPost<'a> {
+    pub authorization: &'a str,
+    pub body: &'a [u8],
+}
+
+async fn send(url: &str, post: Option<JsonPost<'_>>) -> Result<Response<AsyncBody>> {
+    let client = get_static_client()?;
+    log::debug!("Getting data from '{}'", url);
+
+    // Creating an outgoing request. Registry tokens go in the Authorization header, like cargo.
+    let response = match (post, esr_registry::get_registry().token_for_url(url)) {
+        (Some(post), _) => {
+            let request = Request::post(url)
+                .header("Authorization", post.authorization)
+                .header("Content-Type", "application/json")
+                .body(post.body.to_vec())
+                .map_err(|e| EsrError::Other(e.to_string()))?;
+            client.send_async(request).await?
+        },
+        (None, Some(token)) => {
+            let request = Request::get(url)
+                .header("Authorization", token)
+                .body(())
+                .map_err(|e| EsrError::Other(e.to_string()))?;
+            client.send_async(request).await?
+        },
+        (None, None) => client.get_async(url).await?,
+    };
+    Ok(response)
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiErrorDetail {
+    detail: String,
+}
+
+// Error bodies of crates.io (`{"errors":[{"detail":...}]}`), and
+// GitHub/GitLab/Gitea (`{"message":...}`)
+#[derive(Deserialize, Debug)]
+struct ApiErrorBody {
+    #[serde(default)]
+    errors: Vec<ApiErrorDetail>,
+    message: Option<String>,
+}
+
+impl ApiErrorBody {
+    fn message(body: &[u8]) -> Option<String> {
+        let error_body: Self = serde_json::from_slice(body).ok()?;
+        let details: Vec<_> = error_body.errors.into_iter().map(|e| e.detail).collect();
+
+        match details.is_empty() {
+            true => error_body.message,
+            false => Some(details.join(", ")),
+        }
+    }
+}
+
+fn status_error(status: StatusCode, url: &str, body: &[u8]) -> EsrError {
+    let url = esr_cache::cache_key(url);
+    let msg = ApiErrorBody::message(body);
+
+    match status {
+        StatusCode::NOT_FOUND => EsrError::NotFound(url, msg),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => EsrError::Unauthorized(url, msg),
+        _ if status.is_client_error() => EsrError::ClientError(status.as_u16(), url, msg),
+        _ if status.is_server_error() => EsrError::ServerError(status.as_u16(), url, msg),
+        _ => EsrError::Other(format!("Unexpected HTTP status {} from '{}'", status.as_u16(), url)),
+    }
+}
+
+// `<https://...?page=2>; rel="next", <https://...?page=9>; rel="last"` => "https://...?page=2"
+fn next_from_link(link: &str) -> Option<String> {
+    link.split(',')
+        .find(|part| part.split(';').skip(1).any(|param| param.trim() == "rel=\"next\""))
+        .and_then(|part| part.split(';').next())
+        .map(|target| target.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+}
+
+// A single attempt at getting the body of `url`, and the URL of the next
+// page from its `Link` header if any
+async fn fetch(url: &str, post: Option<JsonPost<'_>>) -> Result<(Vec<u8>, Option<String>)> {
+    // Pause if the host's rate limit budget is exhausted, but not for long
+    if let Some(wait) = esr_ratelimit::wait_before(url)? {
+        log::debug!("Rate limit budget exhausted, waiting {}s before getting '{}'", wait.as_secs(), url);
+        smol::Timer::after(wait).await;
+    }
+
+    let mut response = send(url, post).await?;
+    esr_ratelimit::update(url, response.headers());
+
+    // Retry once if the rate limit is reset soon
+    if let Some(secs) = esr_ratelimit::limited_for(url, response.status(), response.headers()) {
+        let host = esr_ratelimit::host_of(url);
+        if secs > esr_ratelimit::MAX_WAIT_SECS {
+            return Err(EsrError::RateLimited(host.into(), secs));
+        }
+
+        log::debug!("Rate limited, retrying '{}' in {}s", url, secs);
+        smol::Timer::after(Duration::from_secs(secs)).await;
+
+        response = send(url, post).await?;
+        esr_ratelimit::update(url, response.headers());
+        if let Some(secs) = esr_ratelimit::limited_for(url, response.status(), response.headers()) {
+            return Err(EsrError::RateLimited(host.into(), secs));
+        }
+    }
+
+    let mut buf = Vec::with_capacity(64*1024);
+    response.copy_to(&mut buf).await?;
+
+    log::debug!("Got data from '{}' (len={})", url, buf.len());
+
+    // Error responses are not returned, so they are not cached either
+    if !response.status().is_success() {
+        return Err(status_error(response.status(), url, &buf));
+    }
+
+    let next = response.headers().get("link")
+        .and_then(|link| link.to_str().ok())
+        .and_then(next_from_link);
+
+    Ok((buf, next))
+}
+
+// Next page URLs are cached separately, as only bodies are cached
+fn next_cache_url(url: &str) -> String {
+    esr_cache::cache_key(url) + "#next"
+}
+
+async fn fetch_with_retries(url: &str, post: Option<JsonPost<'_>>) -> Result<(Vec<u8>, Option<String>)> {
+    let policy = esr_config::get_config().get_retry();
+    let mut attempt = 1;
+    loop {
+        match fetch(url, post).await {
+            Err(ref e) if attempt < policy.get_attempts() && policy.is_retryable(e) => {
+                attempt += 1;
+                let backoff = policy.backoff(attempt);
+                log::debug!("{}. Retrying '{}' in {}ms (attempt {}/{})",
+                            e, esr_cache::cache_key(url), backoff.as_millis(), attempt, policy.get_attempts());
+
+                esr_retry::record_retry();
+                smol::Timer::after(backoff).await;
+            },
+            res => return res,
+        }
+    }
+}
+
+// POST `post` to `url`, with retries. Responses are not cached, as they
+// depend on the body. Callers cache what they need.
+pub(crate) async fn post_bytes(url: &str, post: JsonPost<'_>) -> Result<Vec<u8>> {
+    if esr_cache::is_offline() {
+        return Err(EsrError::Offline(esr_cache::cache_key(url)));
+    }
+
+    fetch_with_retries(url, Some(post)).await.map(|(buf, _)| buf)
+}
+
+// Get `url` from the cache, or fetch it with retries. The next page URL is
+// only returned (and cached) if `linked`.
+async fn get_bytes(url: &str, linked: bool) -> Result<(Vec<u8>, Option<String>)> {
+    if let Some(buf) = esr_cache::get(url) {
+        log::debug!("Got cached data for '{}' (len={})", esr_cache::cache_key(url), buf.len());
+        let next = match linked {
+            true => esr_cache::get(&next_cache_url(url)).and_then(|next| String::from_utf8(next).ok()),
+            false => None,
+        };
+        return Ok((buf, next));
+    }
+
+    if esr_cache::is_offline() {
+        return Err(EsrError::Offline(esr_cache::cache_key(url)));
+    }
+
+    let (buf, next) = fetch_with_retries(url, None).await?;
+    esr_cache::put(url, &buf);
+
+    match (linked, next) {
+        (true, Some(next)) => {
+            esr_cache::put(&next_cache_url(url), next.as_bytes());
+            Ok((buf, Some(next)))
+        },
+        _ => Ok((buf, None)),
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Meta {
     pub total: usize,
@@ -83,6 +279,55 @@ pub trait EsrFromMulti: EsrFrom + Sync + Send + 'static {
     fn get_inner_mut(&mut self) -> &mut Vec<Self::Inner>;
 }
 
+// Lists paginated with `Link` headers, like GitHub's
+#[async_trait]
+pub trait EsrFromLinked: EsrFrom + 'static {
+    // Follow `rel="next"` links from `url`, up to `max_pages` pages
+    async fn from_url_linked(url: &str, max_pages: usize) -> Result<Self> {
+        let (bytes, mut next) = get_bytes(url, true).await?;
+        let mut ret = Self::from_bytes(&*bytes)?;
+
+        for _ in 1..max_pages {
+            let next_url = match next.take() {
+                Some(next_url) => with_token_of(next_url, url),
+                None => break,
+            };
+
+            let (bytes, next_next) = get_bytes(&next_url, true).await?;
+            ret.append(Self::from_bytes(&*bytes)?);
+            next = next_next;
+        }
+
+        Ok(ret)
+    }
+
+    // Owned arguments variants to allow use in task::spawn
+    async fn from_url_linked_owned(url: String, max_pages: usize) -> Result<Self> {
+        Self::from_url_linked(&*url, max_pages).await
+    }
+
+    fn append(&mut self, page: Self);
+}
+
+impl<T> EsrFromLinked for Vec<T> where Vec<T>: EsrFrom, T: Send + Sync + 'static {
+    fn append(&mut self, mut page: Self) {
+        Vec::append(self, &mut page);
+    }
+}
+
+// Next page links don't carry the `access_token` param of `url`
+fn with_token_of(next_url: String, url: &str) -> String {
+    let token_param = url.split(&['?', '&'][..]).find(|param| param.starts_with("access_token="));
+
+    match token_param {
+        Some(param) if !next_url.contains(param) => {
+            let sep = if next_url.contains('?') { '&' } else { '?' };
+            format!("{}{}{}", next_url, sep, param)
+        },
+        _ => next_url,
+    }
+}
+
 #[async_trait]
 pub trait EsrFrom: Sized + Sync + Send + DeserializeOwned {
     // url=id by default
@@ -100,17 +345,7 @@ pub trait EsrFrom: Sized + Sync + Send + DeserializeOwned {
     }
 
     async fn bytes_from_url(url: &str) -> Result<Vec<u8>> {
-        let client = get_static_client()?;
-        log::debug!("Getting data from '{}'", url);
-
-        // Creating an outgoing request.
-        let mut response = client.get_async(url).await?;
-        let mut buf = Vec::with_capacity(64*1024);
-        response.copy_to(&mut buf).await?;
-
-        log::debug!("Got data from '{}' (len={})", url, buf.len());
-
-        Ok(buf)
+        get_bytes(url, false).await.map(|(buf, _)| buf)
     }
 
     async fn bytes_from_id(id: &str) -> Result<Vec<u8>> {
@@ -153,3 +388,121 @@ pub trait EsrFrom: Sized + Sync + Send + DeserializeOwned {
         Self::from_id_with_token(&*id, &*token).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::esr_repo::ContributorInfo;
+    use crate::esr_test_util::{StubServer, StubResponse};
+
+    #[test]
+    fn status_error_kinds() {
+        let url = "https://crates.io/api/v1/crates/foo";
+        let body = br#"{"errors": [{"detail": "invalid request"}]}"#;
+        let status = |code| StatusCode::from_u16(code).unwrap();
+
+        assert!(matches!(status_error(status(404), url, b""), EsrError::NotFound(..)));
+        assert!(matches!(status_error(status(403), url, b""), EsrError::Unauthorized(..)));
+        for &code in &[400, 410, 422, 429] {
+            match status_error(status(code), url, body) {
+                EsrError::ClientError(c, _, Some(ref msg)) => assert_eq!((c, &**msg), (code, "invalid request")),
+                e => panic!("{} mapped to {:?}", code, e),
+            }
+        }
+        for &code in &[500, 502, 503] {
+            assert!(matches!(status_error(status(code), url, b""), EsrError::ServerError(c, _, None) if c == code));
+        }
+        assert!(matches!(status_error(status(304), url, b""), EsrError::Other(_)));
+    }
+
+    #[test]
+    fn only_429s_about_rate_limits_are_rate_limited() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let server = StubServer::start(move |request| {
+            let hit = server_hits.fetch_add(1, Ordering::SeqCst);
+            match &*request.path {
+                "/plain" => StubResponse::json(429, r#"{"errors": [{"detail": "slow down"}]}"#),
+                // Retried right away
+                "/retry-after" if hit == 1 => StubResponse::json(429, "{}").header("Retry-After", "0"),
+                "/retry-after" => StubResponse::json(200, "{}"),
+                "/exhausted" => StubResponse::json(429, "{}")
+                    .header("X-RateLimit-Remaining", "0")
+                    .header("X-RateLimit-Reset", &(unix_now() + 3600).to_string()),
+                _ => StubResponse::json(404, "{}"),
+            }
+        });
+
+        smol::block_on(async {
+            match fetch(&format!("{}/plain", server.url()), None).await {
+                Err(EsrError::ClientError(429, _, Some(ref msg))) => assert_eq!(msg, "slow down"),
+                res => panic!("Expected ClientError(429), got {:?}", res.map(|_| ())),
+            }
+            assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+            assert!(fetch(&format!("{}/retry-after", server.url()), None).await.is_ok());
+            assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+            // Too far away to wait for
+            match fetch(&format!("{}/exhausted", server.url()), None).await {
+                Err(EsrError::RateLimited(_, secs)) => assert!(secs > esr_ratelimit::MAX_WAIT_SECS),
+                res => panic!("Expected RateLimited, got {:?}", res.map(|_| ())),
+            }
+            assert_eq!(hits.load(Ordering::SeqCst), 4);
+        });
+    }
+
+    #[test]
+    fn linked_pages_are_followed() {
+        // Links are absolute, so the handler needs the URL of its own server
+        let base = Arc::new(OnceCell::<String>::new());
+        let server_base = base.clone();
+        let server = StubServer::start(move |request| {
+            let base = server_base.get().expect("Unknown base URL");
+            let (path, query) = request.path.split_at(request.path.find('?').unwrap_or(request.path.len()));
+            let page = |contributions, next: Option<&str>| {
+                let body = format!(r#"[{{"contributions": {}}}]"#, contributions);
+                match next {
+                    Some(next) => StubResponse::json(200, &body)
+                        .header("Link", &format!(r#"<{0}{1}>; rel="next", <{0}{2}?page=3>; rel="last""#, base, next, path)),
+                    None => StubResponse::json(200, &body),
+                }
+            };
+            // The token is carried over to next pages, which don't link to it
+            match query {
+                "?per_page=100&access_token=t" => page(3, Some("/repos/o/r/contributors?per_page=100&page=2")),
+                "?per_page=100&page=2&access_token=t" => page(2, Some("/repos/o/r/contributors?per_page=100&page=3")),
+                "?per_page=100&page=3&access_token=t" => page(1, None),
+                _ => StubResponse::json(404, "{}"),
+            }
+        });
+        base.set(server.url().to_string()).expect("Base URL already set");
+        let url = format!("{}/repos/o/r/contributors?per_page=100&access_token=t", server.url());
+        let contributions = |list: Vec<ContributorInfo>| list.iter().map(|c| c.contributions).collect::<Vec<_>>();
+
+        smol::block_on(async {
+            let first = Vec::<ContributorInfo>::from_url_linked_owned(url.clone(), 1).await.expect("First page");
+            assert_eq!(contributions(first), [3]);
+            assert_eq!(server.requests().len(), 1);
+
+            let two = Vec::<ContributorInfo>::from_url_linked_owned(url.clone(), 2).await.expect("Two pages");
+            assert_eq!(contributions(two), [3, 2]);
+            assert_eq!(server.requests().len(), 3);
+
+            // Stops at the last page
+            let all = Vec::<ContributorInfo>::from_url_linked_owned(url, 10).await.expect("All pages");
+            assert_eq!(contributions(all), [3, 2, 1]);
+            assert_eq!(server.requests().len(), 6);
+        });
+    }
+
+    fn unix_now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
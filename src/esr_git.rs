@@ -0,0 +1,472 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use std::fs;
+
+use serde::Serialize;
+use async_trait::async_trait;
+
+use crate::esr_util;
+use crate::esr_cache::{self, CacheMode};
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_repo::{RepoProvider, RepoInfo, RepoGeneralInfo, ContributorInfo};
+
+// Mirrors are updated at most once in this period, unless refreshing
+const MIRROR_TTL: u64 = 6 * 3600;
+const MONTH_SECS: f64 = 3600.0 * 24.0 * 30.5;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GitStats {
+    commits: usize,
+    authors: usize,
+    // Min. no. of authors responsible for half the commits
+    bus_factor: usize,
+    commits_in_last_12_months: usize,
+    first_commit: String,
+    last_commit: String,
+    #[serde(skip)]
+    tags: Vec<String>,
+}
+
+impl GitStats {
+    pub fn get_commits(&self) -> usize {
+        self.commits
+    }
+
+    pub fn get_authors(&self) -> usize {
+        self.authors
+    }
+
+    pub fn get_bus_factor(&self) -> usize {
+        self.bus_factor
+    }
+
+    pub fn get_commits_in_last_12_months(&self) -> usize {
+        self.commits_in_last_12_months
+    }
+
+    pub fn get_first_commit(&self) -> &str {
+        &self.first_commit
+    }
+
+    pub fn get_last_commit(&self) -> &str {
+        &self.last_commit
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    // Percentage of `versions` of crate `crate_id` with a matching tag
+    // (e.g. "1.2.3", "v1.2.3", "name-1.2.3" or "name-v1.2.3")
+    pub fn tagged_versions_pct(&self, crate_id: &str, versions: &[String]) -> usize {
+        if versions.is_empty() {
+            return 0;
+        }
+
+        let tagged = versions.iter()
+            .filter(|ver| self.tags.iter().any(|tag| tag_matches(tag, crate_id, ver)))
+            .count();
+
+        (tagged as f64 * 100.0 / versions.len() as f64).round() as usize
+    }
+}
+
+// `v?{ver}` or `{crate_id}-v?{ver}`
+fn tag_matches(tag: &str, crate_id: &str, ver: &str) -> bool {
+    let tag = tag.strip_prefix(crate_id)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .filter(|_| !crate_id.is_empty())
+        .unwrap_or(tag);
+    tag.strip_prefix('v').unwrap_or(tag) == ver
+}
+
+// Where mirrors of cloned repos are kept (e.g. `~/.cache/cargo-esr/git`)
+pub fn mirrors_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cargo-esr").join("git"))
+}
+
+// Repo URLs come from crates.io. Only plain transports are allowed, so no
+// `ext::` commands or other transport helpers are run.
+const GIT_PROTOCOLS: &[&str] = &[
+    "protocol.allow=never",
+    "protocol.https.allow=always",
+    "protocol.http.allow=always",
+    "protocol.ssh.allow=always",
+    "protocol.git.allow=always",
+    "protocol.file.allow=always",
+];
+
+fn git(args: &[&str]) -> Result<String> {
+    log::debug!("Running git {}", args.join(" "));
+    let output = Command::new("git")
+        .args(GIT_PROTOCOLS.iter().flat_map(|&protocol| vec!["-c", protocol]))
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(EsrError::Other(format!("git {} failed: {}",
+                                             args.get(0).unwrap_or(&""),
+                                             String::from_utf8_lossy(&output.stderr).trim()))),
+    }
+}
+
+fn updated_recently(marker: &Path) -> bool {
+    fs::metadata(marker)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age < Duration::from_secs(MIRROR_TTL))
+        .unwrap_or(false)
+}
+
+// Could `repo` be taken as an option, or a transport helper (e.g. "ext::sh -c ...")?
+fn is_unsafe_repo(repo: &str) -> bool {
+    repo.starts_with('-') || repo.contains("::") || repo.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+// Clone a mirror of `url`, or update an existing one. Returns the mirror's path.
+fn mirror(url: &str) -> Result<PathBuf> {
+    if is_unsafe_repo(url) {
+        Err(EsrError::Other(format!("Refusing to clone '{}'", url)))?;
+    }
+
+    let dir = mirrors_dir()
+        .ok_or("Failed to get git mirrors dir")?
+        .join(format!("{:016x}.git", esr_cache::fnv1a(url)));
+    let dir_str = dir.to_string_lossy().into_owned();
+    let marker = dir.join("esr-updated");
+
+    match (dir.is_dir(), esr_cache::get_cache_mode()) {
+        (true, CacheMode::Offline) => return Ok(dir),
+        (false, CacheMode::Offline) => return Err(EsrError::Offline(url.into())),
+        (true, CacheMode::Normal) if updated_recently(&marker) => return Ok(dir),
+        (true, _) => {
+            git(&["-C", &dir_str, "remote", "update", "--prune"])?;
+        },
+        (false, _) => {
+            fs::create_dir_all(dir.parent().ok_or("Impossible")?)?;
+            git(&["clone", "--quiet", "--mirror", "--", url, &dir_str])?;
+        },
+    }
+
+    fs::write(&marker, url)?;
+    Ok(dir)
+}
+
+// Also returns per-author commits, most commits first
+fn stats_from_mirror(dir: &Path) -> Result<(GitStats, Vec<usize>)> {
+    let dir_str = &*dir.to_string_lossy();
+
+    // "commit timestamp<TAB>author email", newest first
+    let log = git(&["-C", dir_str, "log", "--no-merges", "--format=%ct%x09%aE", "HEAD"])?;
+    let tags = git(&["-C", dir_str, "tag", "--list"])?;
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let year_ago = now - (12.0 * MONTH_SECS) as i64;
+
+    let mut commits = 0;
+    let mut commits_in_last_12_months = 0;
+    let mut first_commit = i64::max_value();
+    let mut last_commit = i64::min_value();
+    let mut by_author = BTreeMap::new();
+
+    for line in log.lines() {
+        let mut fields = line.splitn(2, '\t');
+        let (ts, author) = match (fields.next().map(|ts| ts.parse::<i64>()), fields.next()) {
+            (Some(Ok(ts)), Some(author)) => (ts, author.to_lowercase()),
+            _ => continue,
+        };
+
+        commits += 1;
+        first_commit = first_commit.min(ts);
+        last_commit = last_commit.max(ts);
+        if ts >= year_ago {
+            commits_in_last_12_months += 1;
+        }
+        *by_author.entry(author).or_insert(0usize) += 1;
+    }
+
+    if commits == 0 {
+        Err("No commits found")?;
+    }
+
+    let mut author_commits: Vec<_> = by_author.values().cloned().collect();
+    author_commits.sort_by(|a, b| b.cmp(a));
+
+    let mut bus_factor = 0;
+    let mut covered = 0;
+    for c in &author_commits {
+        bus_factor += 1;
+        covered += c;
+        if covered * 2 >= commits {
+            break;
+        }
+    }
+
+    let stats = GitStats {
+        commits,
+        authors: author_commits.len(),
+        bus_factor,
+        commits_in_last_12_months,
+        first_commit: esr_util::timestamp_to_iso8601(first_commit),
+        last_commit: esr_util::timestamp_to_iso8601(last_commit),
+        tags: tags.lines().map(String::from).collect(),
+    };
+
+    Ok((stats, author_commits))
+}
+
+fn repo_info_from_url(url: &str) -> Result<RepoInfo> {
+    let dir = mirror(url)?;
+    let (stats, author_commits) = stats_from_mirror(&dir)?;
+
+    let contributors = author_commits
+        .into_iter()
+        .take(100)
+        .map(|contributions| ContributorInfo { contributions })
+        .collect();
+
+    let general_info = RepoGeneralInfo {
+        subscribers_count: 0,
+        created_at: stats.first_commit.clone(),
+        pushed_at: stats.last_commit.clone(),
+    };
+
+    Ok(RepoInfo::new(general_info, Vec::new(), Vec::new(), contributors).with_git_stats(stats))
+}
+
+// "user@host:path", with no '/' before the ':', like git expects
+fn is_scp_like(repo: &str) -> bool {
+    let mut parts = repo.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(user_host), Some(path)) => {
+            let mut user_host = user_host.splitn(2, '@');
+            match (user_host.next(), user_host.next()) {
+                (Some(user), Some(host)) => !user.is_empty() && !host.is_empty()
+                    && !host.contains('/') && !user.contains('/') && !path.is_empty(),
+                _ => false,
+            }
+        },
+        _ => false,
+    }
+}
+
+// Scores repos from their git history, whatever forge they are hosted on.
+// Used instead of the API providers with `--repo-source git`.
+pub struct GitProvider;
+
+impl GitProvider {
+    pub const NAME: &'static str = "git";
+}
+
+#[async_trait]
+impl RepoProvider for GitProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn detect(&self, repo: &str) -> Option<String> {
+        let repo = repo.trim_end_matches('/');
+        if is_unsafe_repo(repo) {
+            return None;
+        }
+
+        let is_url = ["file://", "http://", "https://", "git://", "ssh://"]
+            .iter()
+            .any(|scheme| repo.starts_with(scheme));
+
+        let gh_id = match is_url {
+            true if repo.contains("://github.com/") => esr_util::github_repo(repo),
+            true => None,
+            false => esr_util::github_repo(repo),
+        };
+
+        match (is_url, gh_id) {
+            // Drop "/tree/master" and alike from GitHub URLs, and expand "owner/repo"
+            (_, Some(gh_id)) => Some(format!("https://github.com/{}", gh_id)),
+            // Drop "/-/tree/master" and alike from GitLab URLs
+            (true, None) => Some(repo.split("/-/").next().unwrap_or(repo).into()),
+            // scp-like (e.g. "git@host:owner/repo.git")
+            (false, None) if is_scp_like(repo) => Some(repo.into()),
+            (false, None) => None,
+        }
+    }
+
+    async fn fetch(&self, id: String) -> Result<RepoInfo> {
+        smol::unblock(move || repo_info_from_url(&id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use std::time::SystemTime;
+
+    use super::{git, mirror, stats_from_mirror, tag_matches, GitProvider};
+    use crate::esr_repo::RepoProvider;
+    use crate::esr_util;
+
+    #[test]
+    fn detect_rejects_options_and_transport_helpers() {
+        let unsafe_repos = [
+            "--upload-pack=touch /tmp/esr-pwned@x:y",
+            "-uupload-pack@x:y",
+            "ext::sh -c touch% /tmp/esr-pwned@x:y",
+            "ext::sh@x:y",
+            "fd::3@x:y",
+            "git@host:repo\n--upload-pack=x",
+            "user@host:path with spaces",
+        ];
+
+        for repo in &unsafe_repos {
+            assert_eq!(GitProvider.detect(repo), None, "{}", repo);
+        }
+    }
+
+    #[test]
+    fn detect_accepts_plain_repos() {
+        assert_eq!(GitProvider.detect("https://gitlab.com/group/project/-/tree/master").as_deref(),
+                   Some("https://gitlab.com/group/project"));
+        assert_eq!(GitProvider.detect("https://github.com/owner/repo/tree/master").as_deref(),
+                   Some("https://github.com/owner/repo"));
+        assert_eq!(GitProvider.detect("git@example.com:owner/repo.git").as_deref(),
+                   Some("git@example.com:owner/repo.git"));
+        assert_eq!(GitProvider.detect("owner/repo@x:y"), None);
+        assert_eq!(GitProvider.detect("not a repo"), None);
+    }
+
+    #[test]
+    fn mirror_refuses_unsafe_urls() {
+        let marker = std::env::temp_dir().join(format!("esr-pwned-{}", std::process::id()));
+        let url = format!("ext::sh -c touch% {}", marker.display());
+
+        assert!(mirror(&url).is_err());
+        assert!(mirror("--upload-pack=true").is_err());
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn git_only_allows_plain_transports() {
+        let dir = std::env::temp_dir().join(format!("esr-git-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create test dir");
+
+        // Transport helpers are never run
+        let marker = dir.join("pwned");
+        let helper_url = format!("ext::sh -c touch% {}", marker.display());
+        assert!(git(&["ls-remote", "--", &helper_url]).is_err());
+        assert!(!marker.exists());
+
+        // Plain transports still work
+        let repo = dir.join("repo");
+        let repo_str = repo.to_string_lossy().into_owned();
+        let status = Command::new("git")
+            .args(&["init", "--quiet", &repo_str])
+            .status()
+            .expect("Failed to run git init");
+        assert!(status.success());
+        assert!(git(&["ls-remote", "--", &format!("file://{}", repo_str)]).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tags_match_exact_versions() {
+        assert!(tag_matches("1.2.3", "foo", "1.2.3"));
+        assert!(tag_matches("v1.2.3", "foo", "1.2.3"));
+        assert!(tag_matches("foo-1.2.3", "foo", "1.2.3"));
+        assert!(tag_matches("foo-v1.2.3", "foo", "1.2.3"));
+
+        assert!(!tag_matches("11.2.3", "foo", "1.2.3"));
+        assert!(!tag_matches("v1.2.3-rc.1", "foo", "1.2.3"));
+        assert!(!tag_matches("V1.2.3", "foo", "1.2.3"));
+        assert!(!tag_matches("bar-v1.2.3", "foo", "1.2.3"));
+        assert!(!tag_matches("foo-bar-v1.2.3", "foo", "1.2.3"));
+        assert!(!tag_matches("release/1.2.3", "foo", "1.2.3"));
+        assert!(!tag_matches("-1.2.3", "", "1.2.3"));
+    }
+
+    #[test]
+    fn stats_from_cloned_history() {
+        let dir = std::env::temp_dir().join(format!("esr-git-stats-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create test dir");
+
+        let repo = dir.join("repo");
+        let repo_str = repo.to_string_lossy().into_owned();
+        let run_git = |args: &[&str], date: Option<i64>| {
+            let mut cmd = Command::new("git");
+            cmd.args(&["-C", &repo_str]).args(args);
+            if let Some(date) = date {
+                cmd.env("GIT_AUTHOR_DATE", format!("{} +0000", date))
+                    .env("GIT_COMMITTER_DATE", format!("{} +0000", date));
+            }
+            assert!(cmd.status().expect("Failed to run git").success(), "git {}", args.join(" "));
+        };
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap();
+        let day = 24 * 3600;
+        let first = 1_420_070_400; // 2015-01-01
+
+        // Oldest first. Emails are compared case-insensitively.
+        let commits = [
+            ("a@example.com", first),
+            ("b@example.com", first + day),
+            ("c@example.com", first + 2 * day),
+            ("A@example.com", now - 3 * day),
+            ("d@example.com", now - 2 * day),
+            ("b@example.com", now - day),
+        ];
+
+        fs::create_dir_all(&repo).unwrap();
+        run_git(&["init", "--quiet"], None);
+        for &(email, date) in &commits {
+            let email = format!("user.email={}", email);
+            run_git(&["-c", "user.name=Test", "-c", &email, "commit", "--quiet", "--allow-empty", "-m", "commit"], Some(date));
+        }
+        for tag in &["v1.0.0", "foo-1.1.0", "foo-v1.2.0", "bar-v2.0.0", "x1.3.0"] {
+            run_git(&["tag", tag], None);
+        }
+
+        let mirror_dir = dir.join("mirror.git");
+        let mirror_str = mirror_dir.to_string_lossy().into_owned();
+        git(&["clone", "--quiet", "--mirror", "--", &format!("file://{}", repo_str), &mirror_str]).unwrap();
+        let (stats, author_commits) = stats_from_mirror(&mirror_dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(stats.get_commits(), 6);
+        assert_eq!(stats.get_authors(), 4);
+        assert_eq!(author_commits, [2, 2, 1, 1]);
+        assert_eq!(stats.get_bus_factor(), 2);
+        assert_eq!(stats.get_commits_in_last_12_months(), 3);
+        assert_eq!(stats.get_first_commit(), esr_util::timestamp_to_iso8601(first));
+        assert_eq!(stats.get_last_commit(), esr_util::timestamp_to_iso8601(now - day));
+
+        let versions: Vec<_> = ["1.0.0", "1.1.0", "1.2.0", "2.0.0", "1.3.0"].iter().map(|&v| v.into()).collect();
+        assert_eq!(stats.tagged_versions_pct("foo", &versions), 60);
+        assert_eq!(stats.tagged_versions_pct("foo", &[]), 0);
+    }
+}
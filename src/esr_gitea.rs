@@ -0,0 +1,287 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use async_trait::async_trait;
+
+use crate::esr_from::EsrFrom;
+use crate::esr_util;
+use crate::esr_retry;
+use crate::esr_config;
+use crate::esr_errors::Result;
+use crate::esr_repo::{RepoProvider, RepoInfo, RepoGeneralInfo, PullRequestInfo, IssueInfo, ContributorInfo};
+
+pub const DEFAULT_GITEA_HOSTS: &[&str] = &["codeberg.org"];
+
+// Gitea caps `limit` at its `MAX_RESPONSE_ITEMS` setting (50 by default). So,
+// lists are fetched a page at a time, up to `MAX_LIST_ITEMS` items.
+const PAGE_LIMIT: usize = 50;
+const MAX_LIST_ITEMS: usize = 100;
+// In case an instance caps pages at a few items
+const MAX_LIST_PAGES: usize = 10;
+
+fn gitea_re() -> &'static Result<Regex> {
+    lazy_static! {
+        static ref RE: Result<Regex> =
+            Ok(Regex::new(r"^(?:https?://([^/]+)/|git@([^:/]+):)([^/:]+)/([^/:]+?)(?:\.git)?(?:/.*)?$")?);
+    }
+    &RE
+}
+
+// Base URL (e.g. "https://codeberg.org") of an allowed instance running on `host`
+fn base_url_for_host(host: &str) -> Option<String> {
+    let configured = esr_config::get_config().get_gitea().get_hosts();
+
+    DEFAULT_GITEA_HOSTS.iter()
+        .map(|&h| h)
+        .chain(configured.iter().map(|h| h.as_str()))
+        .map(|h| match h.contains("://") {
+            true => h.trim_end_matches('/').to_string(),
+            false => format!("https://{}", h.trim_end_matches('/')),
+        })
+        .find(|base_url| esr_util::url_host(base_url) == Some(host))
+}
+
+// Get the API URL of a repo (e.g. "https://codeberg.org/api/v1/repos/owner/repo")
+// from its URL. Only codeberg.org and hosts allowed in the config are recognized.
+pub fn gitea_repo(repo: &str) -> Option<String> {
+    let cap = match *gitea_re() {
+        Ok(ref re) => re.captures(repo)?,
+        Err(_) => return None,
+    };
+
+    let host = cap.get(1).or_else(|| cap.get(2))?.as_str();
+    let base_url = base_url_for_host(host)?;
+    Some(format!("{}/api/v1/repos/{}/{}", base_url, &cap[3], &cap[4]))
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaPullRequestInfo {
+    merged_at: Option<String>,
+    number: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaIssueInfo {
+    closed_at: Option<String>,
+    number: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaUser {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaCommitAuthor {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaCommitDetails {
+    author: GiteaCommitAuthor,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaCommitInfo {
+    commit: GiteaCommitDetails,
+    // None if the commit email doesn't match a user
+    author: Option<GiteaUser>,
+}
+
+// =================
+
+type RepoPullRequests = Vec<GiteaPullRequestInfo>;
+
+impl EsrFrom for RepoPullRequests {
+    fn url_from_id(id: &str) -> String {
+        format!("{}/pulls?state=all", id)
+    }
+}
+
+type RepoClosedIssues = Vec<GiteaIssueInfo>;
+
+impl EsrFrom for RepoClosedIssues {
+    fn url_from_id(id: &str) -> String {
+        format!("{}/issues?state=closed&type=issues&limit=1", id)
+    }
+}
+
+type RepoCommits = Vec<GiteaCommitInfo>;
+
+impl EsrFrom for RepoCommits {
+    fn url_from_id(id: &str) -> String {
+        format!("{}/commits?stat=false&verification=false&files=false", id)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaRepoGeneralInfo {
+    watchers_count: usize,
+    created_at: String,
+    updated_at: String,
+}
+
+impl EsrFrom for GiteaRepoGeneralInfo {
+    fn url_from_id(id: &str) -> String {
+        id.into()
+    }
+}
+
+// =================
+
+// The first `MAX_LIST_ITEMS` items of a list. Pages shorter than the first one
+// are the last, as instances may cap pages below `PAGE_LIMIT`.
+async fn list_from_id<T>(id: String) -> Result<Vec<T>> where Vec<T>: EsrFrom, T: Send + Sync + 'static {
+    let url = Vec::<T>::url_from_id(&id);
+    let mut ret = Vec::with_capacity(MAX_LIST_ITEMS);
+    let mut page_len = PAGE_LIMIT;
+
+    for page in 1..=MAX_LIST_PAGES {
+        let items = Vec::<T>::from_url(&format!("{}&limit={}&page={}", url, PAGE_LIMIT, page)).await?;
+        let last = items.is_empty() || (page > 1 && items.len() < page_len);
+        page_len = match page {
+            1 => items.len(),
+            _ => page_len,
+        };
+
+        ret.extend(items);
+        if last || ret.len() >= MAX_LIST_ITEMS {
+            break;
+        }
+    }
+
+    ret.truncate(MAX_LIST_ITEMS);
+    Ok(ret)
+}
+
+// Gitea has no contributors endpoint. So, we count the authors of the last
+// 100 commits instead. By user if known, by email otherwise.
+fn contributors_from_commits(commits: &[GiteaCommitInfo]) -> Vec<ContributorInfo> {
+    let mut by_author = BTreeMap::new();
+    for commit in commits {
+        let author = match commit.author {
+            Some(ref user) => &user.login,
+            None => &commit.commit.author.email,
+        };
+        *by_author.entry(author).or_insert(0) += 1;
+    }
+
+    let mut contributions: Vec<usize> = by_author.into_iter().map(|(_, c)| c).collect();
+    contributions.sort_by(|a, b| b.cmp(a));
+
+    contributions
+        .into_iter()
+        .map(|contributions| ContributorInfo { contributions })
+        .collect()
+}
+
+// Fetch a Gitea/Forgejo repo's info, mapped onto the normalized repo info.
+// The last update stands in for the last push.
+async fn repo_info_from_repo(repo: String) -> Result<RepoInfo> {
+    // pulls is slow, so we spawn it first
+    let pull_requests_fut = esr_retry::spawn(list_from_id::<GiteaPullRequestInfo>(repo.clone()));
+    let closed_issues_fut = esr_retry::spawn(RepoClosedIssues::from_id_owned(repo.clone()));
+    let commits_fut = esr_retry::spawn(list_from_id::<GiteaCommitInfo>(repo.clone()));
+    let general_info_fut = esr_retry::spawn(GiteaRepoGeneralInfo::from_id_owned(repo));
+
+    let general_info = general_info_fut.await?;
+    let convert_date = |date: &Option<String>| date.as_ref().map(|d| esr_util::to_iso8601(d));
+
+    let general_info = RepoGeneralInfo {
+        subscribers_count: general_info.watchers_count,
+        created_at: esr_util::to_iso8601(&general_info.created_at),
+        pushed_at: esr_util::to_iso8601(&general_info.updated_at),
+    };
+
+    let closed_issues = closed_issues_fut.await?
+        .iter()
+        .map(|issue| IssueInfo { closed_at: convert_date(&issue.closed_at), number: issue.number })
+        .collect();
+
+    let pull_requests = pull_requests_fut.await?
+        .iter()
+        .map(|pr| PullRequestInfo { merged_at: convert_date(&pr.merged_at), number: pr.number })
+        .collect();
+
+    let contributors = contributors_from_commits(&commits_fut.await?);
+
+    Ok(RepoInfo::new(general_info, closed_issues, pull_requests, contributors))
+}
+
+pub struct GiteaProvider;
+
+#[async_trait]
+impl RepoProvider for GiteaProvider {
+    fn name(&self) -> &str {
+        "Gitea"
+    }
+
+    fn detect(&self, repo: &str) -> Option<String> {
+        gitea_repo(repo)
+    }
+
+    async fn fetch(&self, id: String) -> Result<RepoInfo> {
+        repo_info_from_repo(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_from_id, GiteaPullRequestInfo};
+    use crate::esr_test_util::{StubServer, StubResponse};
+
+    // Serves `total` PRs, capping pages at `cap` items like `MAX_RESPONSE_ITEMS`
+    fn pulls_server(total: usize, cap: usize) -> StubServer {
+        StubServer::start(move |request| {
+            let param = |name: &str| request.path.split(&['?', '&'][..])
+                .find_map(|p| p.strip_prefix(name))
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(1);
+            let (limit, page) = (param("limit=").min(cap), param("page="));
+
+            let pulls: Vec<_> = ((page - 1) * limit..(page * limit).min(total))
+                .map(|number| format!(r#"{{"number": {}, "merged_at": null}}"#, number))
+                .collect();
+            StubResponse::json(200, &format!("[{}]", pulls.join(",")))
+        })
+    }
+
+    fn list_len(server: &StubServer) -> usize {
+        let repo = format!("{}/api/v1/repos/owner/repo", server.url());
+        smol::block_on(list_from_id::<GiteaPullRequestInfo>(repo)).expect("Failed to get pulls").len()
+    }
+
+    #[test]
+    fn gitea_lists_are_paginated() {
+        let server = pulls_server(120, 50);
+        assert_eq!(list_len(&server), 100);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn gitea_lists_with_lower_caps() {
+        let server = pulls_server(75, 30);
+        assert_eq!(list_len(&server), 75);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn gitea_short_lists() {
+        let server = pulls_server(20, 50);
+        assert_eq!(list_len(&server), 20);
+        assert!(server.requests().len() <= 2);
+    }
+}
@@ -9,28 +9,18 @@
     file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
 */
 
-use serde::Deserialize;
+use async_trait::async_trait;
 
-use crate::esr_from::EsrFrom;
+use crate::esr_from::{EsrFrom, EsrFromLinked};
+use crate::esr_config::{self, GitHubBackend};
+use crate::esr_github_graphql;
 use crate::esr_util;
+use crate::esr_retry;
 use crate::esr_errors::Result;
+use crate::esr_repo::{RepoProvider, RepoInfo, RepoGeneralInfo, PullRequestInfo, IssueInfo, ContributorInfo};
 
-#[derive(Deserialize, Debug)]
-struct PullRequestInfo {
-    merged_at: Option<String>,
-    number: usize,
-}
-
-#[derive(Deserialize, Debug)]
-struct IssueInfo {
-    closed_at: Option<String>,
-    number: usize,
-}
-
-#[derive(Deserialize, Debug)]
-struct ContributorInfo {
-    contributions: usize,
-}
+// Moved to esr_repo, as they are not GitHub-specific
+pub use crate::esr_repo::{RepoScoreInfo, RepoInfoWithScore};
 
 // =================
 
@@ -62,14 +52,6 @@ impl EsrFrom for RepoContributors {
     }
 }
 
-#[derive(Deserialize, Debug)]
-struct RepoGeneralInfo {
-    subscribers_count: usize,
-    created_at: String,
-    // Same as created_at if the repo is empty
-    pushed_at: String,
-}
-
 impl EsrFrom for RepoGeneralInfo {
     fn url_from_id(id: &str) -> String {
         let url = String::from("https://api.github.com/repos/:id");
@@ -79,203 +61,52 @@ impl EsrFrom for RepoGeneralInfo {
 
 // =================
 
-pub struct RepoInfo {
-    general_info: RepoGeneralInfo,
-    last_100_closed_issues: RepoClosedIssues,
-    last_100_pull_requests: RepoPullRequests,
-    top_100_contributors: RepoContributors,
+pub struct GitHubProvider {
+    token: String,
 }
 
-impl RepoInfo {
-    pub fn from_id(_: &str) -> Result<Self> {
-        Err("Unimplemented: use  from_id_with_token()")?
-    }
-
-    pub async fn from_id_with_token(id: String, token: String) -> Result<Self> {
-        // pulls is slow, so we spawn it first
-        let last_100_pull_requests_fut = smol::spawn(RepoPullRequests::from_id_with_token_owned(id.clone(), token.clone()));
-        let last_100_closed_issues_fut = smol::spawn(RepoClosedIssues::from_id_with_token_owned(id.clone(), token.clone()));
-        let top_100_contributors_fut = smol::spawn(RepoContributors::from_id_with_token_owned(id.clone(), token.clone()));
-        let general_info_fut = smol::spawn(RepoGeneralInfo::from_id_with_token_owned(id, token));
+impl GitHubProvider {
+    pub const NAME: &'static str = "GitHub";
 
-        Ok(Self {
-            general_info: general_info_fut.await?,
-            last_100_closed_issues: last_100_closed_issues_fut.await?,
-            last_100_pull_requests: last_100_pull_requests_fut.await?,
-            top_100_contributors: top_100_contributors_fut.await?,
-        })
+    pub fn new(token: String) -> Self {
+        Self { token }
     }
 }
 
-pub struct RepoScoreInfo {
-    subscribers: f64,
-    contributors_up_to_100: usize,
-    commits_from_upto_100_contributors: f64,
-    secondary_contribution_pct: usize,
-    tertiary_contribution_pct: usize,
-    merged_pull_requests_in_last_100: usize,
-    months_since_last_pr_merged: f64,
-    months_since_last_issue_closed: f64,
-    push_span_in_months: f64,
-    months_since_last_push: f64,
-}
-
-impl RepoScoreInfo {
-    fn from_repo_info(repo_info: &RepoInfo) -> Result<Self> {
-        let general_info = &repo_info.general_info;
-
-        // Days active, months since last push
-        let push_span_in_months =esr_util::span_in_months(&general_info.created_at,
-                                                          &general_info.pushed_at)?;
-        let months_since_last_push = esr_util::age_in_months(&general_info.pushed_at)?;
-
-        // Get subscribers count
-        let subscribers = general_info.subscribers_count as f64;
-
-        // Get contributor count, commit count using contributors info.
-        if repo_info.top_100_contributors.is_empty() {
-            Err("Empty contributors list")?;
-        }
-
-        let contributors_up_to_100 = repo_info.top_100_contributors.len();
-
-        let commits_from_upto_100_contributors = repo_info.top_100_contributors
-            .iter()
-            .map(|contributor| contributor.contributions)
-            .sum::<usize>() as f64;
-
-        // Secondary and Tertiary contribution pct
-        let commits = commits_from_upto_100_contributors;
-        let top_committer_contrib = repo_info.top_100_contributors[0].contributions as f64 / commits;
-        let second_committer_contrib = repo_info.top_100_contributors.get(1)
-            .map(|c| c.contributions as f64 / commits)
-            .unwrap_or(0_f64);
-
-
-        let secondary_contribution_pct = ((1.0 - top_committer_contrib) * 100.0).ceil() as usize;
-        let tertiary_contribution_pct = ((1.0 - top_committer_contrib - second_committer_contrib) * 100.0).ceil() as usize;
-
-        // merged pull requests in last 100, months since last merged
-        let merged_pull_requests_in_last_100 = repo_info.last_100_pull_requests
-            .iter()
-            .filter(|pr| pr.merged_at.is_some())
-            .count();
-
-        let last_pr_merged_opt = repo_info.last_100_pull_requests
-            .iter()
-            .filter(|pr| pr.merged_at.is_some())
-            .nth(0);
-
-        let months_since_last_pr_merged = match last_pr_merged_opt {
-            Some(pr) => esr_util::age_in_months(pr.merged_at.as_ref().ok_or("Impossible")?)?,
-            None => esr_util::age_in_months(&general_info.created_at)?,
-        };
-
-        // months since last closed
-        let last_issue_closed_opt = repo_info.last_100_closed_issues.get(0);
-
-        let months_since_last_issue_closed = match last_issue_closed_opt {
-            Some(issue) => esr_util::age_in_months(issue.closed_at.as_ref().ok_or("Impossible")?)?,
-            None => esr_util::age_in_months(&general_info.created_at)?,
-        };
-
-        // Done
-        Ok(Self {
-            subscribers,
-            contributors_up_to_100,
-            commits_from_upto_100_contributors,
-            secondary_contribution_pct,
-            tertiary_contribution_pct,
-            push_span_in_months,
-            merged_pull_requests_in_last_100,
-            months_since_last_pr_merged,
-            months_since_last_issue_closed,
-            months_since_last_push,
-        })
+#[async_trait]
+impl RepoProvider for GitHubProvider {
+    fn name(&self) -> &str {
+        Self::NAME
     }
 
-    fn mk_score(&self) -> (Vec<(String, String, String)>, f64, f64) {
-        let mut positive_score = 0.0;
-        let mut negative_score = 0.0;
-        let mut table = Vec::with_capacity(9);
-
-        // +ve
-        score_add!(table, positive_score, self.subscribers.powf(0.5), 8.0);
-        score_add!(table, positive_score, self.contributors_up_to_100, 3.0);
-        score_add!(table,
-                   positive_score,
-                   self.commits_from_upto_100_contributors.powf(0.5),
-                   2.0);
-
-        // We only take secondary/tertiary contribution into account if the repo has >= 50 commits
-        if self.commits_from_upto_100_contributors >= 50.0 {
-            score_add!(table, positive_score, self.secondary_contribution_pct, 2.5);
-            score_add!(table, positive_score, self.tertiary_contribution_pct, 5.0);
-        }
-
-        score_add!(table,
-                   positive_score,
-                   self.push_span_in_months.powf(0.5),
-                   5.0);
-        score_add!(table,
-                   positive_score,
-                   self.merged_pull_requests_in_last_100,
-                   2.5);
-
-        // -ve
-        score_add!(table,
-                   negative_score,
-                   self.months_since_last_pr_merged.powf(1.5),
-                   -1.0);
-        score_add!(table,
-                   negative_score,
-                   self.months_since_last_issue_closed.powf(1.5),
-                   -1.0);
-        score_add!(table,
-                   negative_score,
-                   self.months_since_last_push.powf(1.5),
-                   -4.0);
-
-        (table, positive_score, negative_score)
+    fn detect(&self, repo: &str) -> Option<String> {
+        esr_util::github_repo(repo)
     }
-}
 
-pub struct RepoInfoWithScore {
-    repo_info: RepoInfo,
-    repo_score_info: RepoScoreInfo,
-    score_positive: f64,
-    score_negative: f64,
-    score_table: Vec<(String, String, String)>,
-}
-
-impl RepoInfoWithScore {
-    pub async fn from_id_with_token(id: String, token: String) -> Result<Self> {
-        let repo_info = RepoInfo::from_id_with_token(id, token).await?;
-        let repo_score_info = RepoScoreInfo::from_repo_info(&repo_info)?;
-        let (score_table, score_positive, score_negative) = repo_score_info.mk_score();
-
-        Ok(Self {
-            repo_info,
-            repo_score_info,
-            score_positive,
-            score_negative,
-            score_table,
-        })
-    }
-
-    pub fn get_info(&self) -> &RepoInfo {
-        &self.repo_info
-    }
-
-    pub fn get_score_info(&self) -> &RepoScoreInfo {
-        &self.repo_score_info
-    }
+    async fn fetch(&self, id: String) -> Result<RepoInfo> {
+        let token = self.token.clone();
 
-    pub fn get_score_tuple(&self) -> (f64, f64) {
-        (self.score_positive, self.score_negative)
-    }
+        let github_config = esr_config::get_config().get_github();
+        if github_config.get_backend() == GitHubBackend::GraphQl {
+            return esr_github_graphql::fetch_repo(id, token).await;
+        }
 
-    pub fn get_score_table(&self) -> &[(String, String, String)] {
-        &self.score_table
+        let contributors_url = RepoContributors::url_from_id_and_token(&id, &token);
+        let top_100_contributors_fut = esr_retry::spawn(RepoContributors::from_url_linked_owned(
+            contributors_url, github_config.get_max_contributors_pages()));
+
+        // pulls is slow, so we spawn it before the rest
+        let pull_requests_url = RepoPullRequests::url_from_id_and_token(&id, &token);
+        let last_100_pull_requests_fut = esr_retry::spawn(RepoPullRequests::from_url_linked_owned(
+            pull_requests_url, github_config.get_max_pull_requests_pages()));
+        let last_100_closed_issues_fut = esr_retry::spawn(RepoClosedIssues::from_id_with_token_owned(id.clone(), token.clone()));
+        let general_info_fut = esr_retry::spawn(RepoGeneralInfo::from_id_with_token_owned(id, token));
+
+        Ok(RepoInfo::new(
+            general_info_fut.await?,
+            last_100_closed_issues_fut.await?,
+            last_100_pull_requests_fut.await?,
+            top_100_contributors_fut.await?,
+        ))
     }
 }
@@ -0,0 +1,647 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use once_cell::sync::OnceCell;
+use smol::channel::{self, Sender};
+use futures::future;
+
+use crate::esr_from::{self, JsonPost};
+use crate::esr_cache;
+use crate::esr_config;
+use crate::esr_retry;
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_repo::{RepoInfo, RepoGeneralInfo, PullRequestInfo, IssueInfo, ContributorInfo};
+
+// How long the first queued repo waits for others to join its batch.
+// Repos of search results are looked up at about the same time.
+const BATCH_WINDOW_MS: u64 = 100;
+
+// Same orders as the REST endpoints used by `GitHubProvider`. REST lists
+// PRs as issues, so the last closed issue is the newest of both.
+const REPO_FRAGMENT: &str = "fragment repo on Repository {
+  createdAt
+  pushedAt
+  watchers { totalCount }
+  issues(first: 1, states: CLOSED, orderBy: {field: CREATED_AT, direction: DESC}) {
+    nodes { number createdAt closedAt }
+  }
+  closedPullRequests: pullRequests(first: 1, states: [CLOSED, MERGED], orderBy: {field: CREATED_AT, direction: DESC}) {
+    nodes { number createdAt closedAt }
+  }
+  pullRequests(first: 100, orderBy: {field: CREATED_AT, direction: DESC}) { ...pulls }
+  defaultBranchRef { target { ... on Commit { history(first: 100) { ...commits } } } }
+}";
+
+const PULLS_FRAGMENT: &str = "fragment pulls on PullRequestConnection {
+  nodes { number mergedAt }
+  pageInfo { hasNextPage endCursor }
+}";
+
+const COMMITS_FRAGMENT: &str = "fragment commits on CommitHistoryConnection {
+  totalCount
+  nodes { author { user { login } } }
+  pageInfo { hasNextPage endCursor }
+}";
+
+// Where, and how much of, repos are queried
+#[derive(Debug, Clone)]
+struct GqlSettings {
+    endpoint: String,
+    batch_size: usize,
+    pull_requests_pages: usize,
+    history_pages: usize,
+}
+
+impl GqlSettings {
+    fn from_config() -> Self {
+        let github_config = esr_config::get_config().get_github();
+        let graphql_config = github_config.get_graphql();
+        Self {
+            endpoint: graphql_config.get_endpoint().into(),
+            batch_size: graphql_config.get_batch_size(),
+            pull_requests_pages: github_config.get_max_pull_requests_pages(),
+            history_pages: graphql_config.get_history_pages(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlCount {
+    total_count: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlPageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlPullRequest {
+    number: usize,
+    merged_at: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlPullRequests {
+    nodes: Vec<GqlPullRequest>,
+    page_info: GqlPageInfo,
+}
+
+// A closed issue or PR
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlIssue {
+    number: usize,
+    created_at: String,
+    closed_at: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlIssues {
+    nodes: Vec<GqlIssue>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlUser {
+    login: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlCommitAuthor {
+    // None if the author's email is not linked to a GitHub user
+    user: Option<GqlUser>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlCommit {
+    author: Option<GqlCommitAuthor>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlHistory {
+    total_count: usize,
+    nodes: Vec<GqlCommit>,
+    page_info: GqlPageInfo,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlTarget {
+    // None if the target is not a commit
+    history: Option<GqlHistory>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct GqlRef {
+    target: Option<GqlTarget>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlRepo {
+    created_at: String,
+    // None if the repo is empty
+    pushed_at: Option<String>,
+    watchers: GqlCount,
+    issues: GqlIssues,
+    closed_pull_requests: GqlIssues,
+    pull_requests: GqlPullRequests,
+    // None if the repo is empty
+    default_branch_ref: Option<GqlRef>,
+}
+
+impl GqlRepo {
+    fn history_mut(&mut self) -> Option<&mut GqlHistory> {
+        self.default_branch_ref.as_mut()?.target.as_mut()?.history.as_mut()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GqlError {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    message: String,
+    #[serde(default)]
+    path: Vec<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GqlResponse {
+    // alias => repo, null if it failed
+    data: Option<BTreeMap<String, Option<Value>>>,
+    #[serde(default)]
+    errors: Vec<GqlError>,
+}
+
+impl GqlResponse {
+    // The repo queried as `alias`, or the error reported for it
+    fn take_repo(&mut self, alias: &str, id: &str) -> Result<GqlRepo> {
+        let repo = self.data.as_mut().and_then(|data| data.remove(alias)).and_then(|repo| repo);
+        if let Some(repo) = repo {
+            return Ok(serde_json::from_value(repo)?);
+        }
+
+        let error = self.errors.iter()
+            .find(|e| e.path.first().and_then(|p| p.as_str()) == Some(alias))
+            .or_else(|| self.errors.iter().find(|e| e.path.is_empty()));
+
+        match error {
+            Some(e) if e.kind.as_deref() == Some("NOT_FOUND") => {
+                Err(EsrError::NotFound(format!("https://github.com/{}", id), Some(e.message.clone())))
+            },
+            Some(e) => Err(EsrError::Other(format!("GraphQL query of '{}' failed: {}", id, e.message))),
+            None => Err(EsrError::Other(format!("GraphQL query of '{}' returned no data", id))),
+        }
+    }
+}
+
+// Where repo results are cached, as POST responses are not
+fn repo_url(id: &str, settings: &GqlSettings) -> String {
+    format!("{}?repo={}&pr_pages={}&history_pages={}",
+            settings.endpoint, id, settings.pull_requests_pages, settings.history_pages)
+}
+
+// `repository(owner: "o", name: "r")`, with the names escaped
+fn repository_field(id: &str) -> Result<String> {
+    let mut parts = id.splitn(2, '/');
+    let (owner, name) = match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) => (owner, name),
+        _ => Err(EsrError::Other(format!("Invalid GitHub repo '{}'", id)))?,
+    };
+
+    Ok(format!("repository(owner: {}, name: {})",
+               serde_json::to_string(owner)?, serde_json::to_string(name)?))
+}
+
+async fn post_query(query: String, endpoint: &str, token: &str) -> Result<GqlResponse> {
+    let authorization = format!("bearer {}", token);
+    let body = serde_json::to_vec(&serde_json::json!({ "query": query }))?;
+
+    let post = JsonPost { authorization: &authorization, body: &body };
+    let bytes = esr_from::post_bytes(endpoint, post).await?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+// `selection` of the `repository` of `id` alone, e.g. the next page of a list
+async fn query_repository<T: DeserializeOwned>(id: &str, selection: String, fragment: &str,
+                                               settings: &GqlSettings, token: &str) -> Result<T> {
+    let query = format!("query {{\n  {} {{\n    {}\n  }}\n}}\n{}", repository_field(id)?, selection, fragment);
+
+    let mut response = post_query(query, &settings.endpoint, token).await?;
+    match response.data.as_mut().and_then(|data| data.remove("repository")) {
+        Some(Some(repo)) => Ok(serde_json::from_value(repo)?),
+        _ => Err(response.take_repo("repository", id).err()
+                 .unwrap_or_else(|| EsrError::from("GraphQL query returned no data"))),
+    }
+}
+
+// =================
+
+struct Queued {
+    id: String,
+    // Ids retries are counted against, of the task waiting for `id`
+    scope: Vec<String>,
+    tx: Sender<Result<GqlRepo>>,
+}
+
+// Repos waiting for a batch, by the (endpoint, token) they are queried with
+type Queue = HashMap<(String, String), Vec<Queued>>;
+
+fn get_queue() -> &'static Mutex<Queue> {
+    static QUEUE: OnceCell<Mutex<Queue>> = OnceCell::new();
+    QUEUE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Errors are not `Clone`. Failing a whole batch gives each repo its own copy.
+fn batch_error(e: &EsrError) -> EsrError {
+    match *e {
+        EsrError::NotFound(ref url, ref msg) => EsrError::NotFound(url.clone(), msg.clone()),
+        EsrError::Unauthorized(ref url, ref msg) => EsrError::Unauthorized(url.clone(), msg.clone()),
+        EsrError::RateLimited(ref host, secs) => EsrError::RateLimited(host.clone(), secs),
+        EsrError::ClientError(status, ref url, ref msg) => EsrError::ClientError(status, url.clone(), msg.clone()),
+        EsrError::ServerError(status, ref url, ref msg) => EsrError::ServerError(status, url.clone(), msg.clone()),
+        EsrError::Offline(ref url) => EsrError::Offline(url.clone()),
+        ref e => EsrError::Other(e.to_string()),
+    }
+}
+
+// One query for all repos in `batch`, aliased "r0", "r1", ...
+async fn run_batch(batch: Vec<Queued>, endpoint: String, token: String) {
+    let fields: Result<Vec<_>> = batch.iter()
+        .enumerate()
+        .map(|(i, queued)| repository_field(&queued.id).map(|field| format!("  r{}: {} {{ ...repo }}", i, field)))
+        .collect();
+
+    let mut res = match fields {
+        Ok(fields) => {
+            log::debug!("Querying {} GitHub repo(s) in one GraphQL request", batch.len());
+            let query = format!("query {{\n{}\n}}\n{}\n{}\n{}",
+                                fields.join("\n"), REPO_FRAGMENT, PULLS_FRAGMENT, COMMITS_FRAGMENT);
+            // The query is made on behalf of all tasks in the batch
+            let mut scope: Vec<_> = batch.iter().flat_map(|queued| queued.scope.iter().cloned()).collect();
+            scope.sort();
+            scope.dedup();
+            esr_retry::scoped(scope, post_query(query, &endpoint, &token)).await
+        },
+        Err(e) => Err(e),
+    };
+
+    for (i, queued) in batch.into_iter().enumerate() {
+        let repo = match res {
+            Ok(ref mut response) => response.take_repo(&format!("r{}", i), &queued.id),
+            Err(ref e) => Err(batch_error(e)),
+        };
+        // The receiver is only gone if its task was cancelled
+        let _ = queued.tx.send(repo).await;
+    }
+}
+
+// Queue `id` for the next batch sent to the same endpoint with the same
+// token. The first queued repo starts the batches, after giving others some
+// time to join.
+async fn query_batched(id: String, settings: &GqlSettings, token: String) -> Result<GqlRepo> {
+    let (tx, rx) = channel::bounded(1);
+    let key = (settings.endpoint.clone(), token);
+
+    let first = {
+        let mut queue = get_queue().lock().map_err(|_| EsrError::from("GraphQL queue poisoned"))?;
+        let queued = queue.entry(key.clone()).or_insert_with(Vec::new);
+        queued.push(Queued { id, scope: esr_retry::current_ids(), tx });
+        queued.len() == 1
+    };
+
+    if first {
+        let batch_size = settings.batch_size;
+        smol::spawn(async move {
+            smol::Timer::after(Duration::from_millis(BATCH_WINDOW_MS)).await;
+
+            let queued = match get_queue().lock() {
+                Ok(mut queue) => queue.remove(&key).unwrap_or_default(),
+                Err(_) => return,
+            };
+            let (endpoint, token) = key;
+
+            let mut batches = Vec::new();
+            let mut queued = queued.into_iter().peekable();
+            while queued.peek().is_some() {
+                batches.push(queued.by_ref().take(batch_size).collect::<Vec<_>>());
+            }
+
+            future::join_all(batches.into_iter().map(|batch| run_batch(batch, endpoint.clone(), token.clone()))).await;
+        }).detach();
+    }
+
+    rx.recv().await.map_err(|_| EsrError::from("GraphQL batch dropped"))?
+}
+
+// Follow `pullRequests` cursors, up to the configured max pages
+async fn more_pull_requests(id: &str, repo: &mut GqlRepo, settings: &GqlSettings, token: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct GqlPullsPage {
+        pull_requests: GqlPullRequests,
+    }
+
+    for _ in 1..settings.pull_requests_pages {
+        let cursor = match repo.pull_requests.page_info {
+            GqlPageInfo { has_next_page: true, end_cursor: Some(ref cursor) } => cursor.clone(),
+            _ => break,
+        };
+
+        let selection = format!("pullRequests(first: 100, after: {}, \
+                                 orderBy: {{field: CREATED_AT, direction: DESC}}) {{ ...pulls }}",
+                                serde_json::to_string(&cursor)?);
+        let page: GqlPullsPage = query_repository(id, selection, PULLS_FRAGMENT, settings, token).await?;
+
+        repo.pull_requests.nodes.extend(page.pull_requests.nodes);
+        repo.pull_requests.page_info = page.pull_requests.page_info;
+    }
+
+    Ok(())
+}
+
+// Follow default branch `history` cursors, up to the configured max pages
+async fn more_history(id: &str, repo: &mut GqlRepo, settings: &GqlSettings, token: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct GqlHistoryPage {
+        default_branch_ref: Option<GqlRef>,
+    }
+
+    for _ in 1..settings.history_pages {
+        let history = match repo.history_mut() {
+            Some(history) => history,
+            None => break,
+        };
+        let cursor = match history.page_info {
+            GqlPageInfo { has_next_page: true, end_cursor: Some(ref cursor) } => cursor.clone(),
+            _ => break,
+        };
+
+        let selection = format!("defaultBranchRef {{ target {{ ... on Commit {{ \
+                                 history(first: 100, after: {}) {{ ...commits }} }} }} }}",
+                                serde_json::to_string(&cursor)?);
+        let page: GqlHistoryPage = query_repository(id, selection, COMMITS_FRAGMENT, settings, token).await?;
+
+        match page.default_branch_ref.and_then(|r| r.target).and_then(|t| t.history) {
+            Some(page) => {
+                history.nodes.extend(page.nodes);
+                history.page_info = page.page_info;
+            },
+            // e.g. the default branch was just deleted
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+// Commits of users in the fetched `history`, most first, scaled to all
+// commits of the default branch. Like REST contributors, commits by authors
+// not linked to a GitHub user are not attributed to anyone.
+fn contributors(history: Option<GqlHistory>) -> Vec<ContributorInfo> {
+    let history = match history {
+        Some(history) if !history.nodes.is_empty() => history,
+        _ => return Vec::new(),
+    };
+
+    let mut commits_by_login = HashMap::new();
+    for login in history.nodes.iter().filter_map(|c| Some(&c.author.as_ref()?.user.as_ref()?.login)) {
+        *commits_by_login.entry(login).or_insert(0) += 1;
+    }
+
+    let scale = history.total_count.max(history.nodes.len()) as f64 / history.nodes.len() as f64;
+    let mut contributions: Vec<_> = commits_by_login.values()
+        .map(|&commits| ((commits as f64 * scale).round() as usize).max(1))
+        .collect();
+    contributions.sort_unstable_by(|a, b| b.cmp(a));
+
+    contributions.into_iter()
+        .map(|contributions| ContributorInfo { contributions })
+        .collect()
+}
+
+async fn fetch_repo_with(id: String, settings: &GqlSettings, token: String) -> Result<RepoInfo> {
+    let url = repo_url(&id, settings);
+
+    let repo = match esr_cache::get(&url) {
+        Some(bytes) => serde_json::from_slice(&bytes)?,
+        None if esr_cache::is_offline() => Err(EsrError::Offline(esr_cache::cache_key(&url)))?,
+        None if token.is_empty() => Err("GitHub's GraphQL API requires a token")?,
+        None => {
+            let mut repo = query_batched(id.clone(), settings, token.clone()).await?;
+            more_pull_requests(&id, &mut repo, settings, &token).await?;
+            more_history(&id, &mut repo, settings, &token).await?;
+            esr_cache::put(&url, &serde_json::to_vec(&repo)?);
+            repo
+        },
+    };
+
+    let GqlRepo { created_at, pushed_at, watchers, issues, closed_pull_requests, pull_requests, default_branch_ref } = repo;
+
+    let general_info = RepoGeneralInfo {
+        subscribers_count: watchers.total_count,
+        pushed_at: pushed_at.unwrap_or_else(|| created_at.clone()),
+        created_at,
+    };
+
+    // ISO 8601 UTC dates sort chronologically
+    let closed_issues = issues.nodes
+        .into_iter()
+        .chain(closed_pull_requests.nodes)
+        .max_by(|a, b| a.created_at.cmp(&b.created_at))
+        .map(|issue| IssueInfo { closed_at: issue.closed_at, number: issue.number })
+        .into_iter()
+        .collect();
+
+    let pull_requests = pull_requests.nodes
+        .into_iter()
+        .map(|pr| PullRequestInfo { merged_at: pr.merged_at, number: pr.number })
+        .collect();
+
+    let history = default_branch_ref.and_then(|r| r.target).and_then(|t| t.history);
+
+    Ok(RepoInfo::new(general_info, closed_issues, pull_requests, contributors(history)))
+}
+
+// Repo info of GitHub repo `id` ("owner/repo"), with contributors counted
+// over recent commits of the default branch. Lookups of concurrently scored
+// repos share queries.
+pub(crate) async fn fetch_repo(id: String, token: String) -> Result<RepoInfo> {
+    fetch_repo_with(id, &GqlSettings::from_config(), token).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use regex::Regex;
+    use serde_json::json;
+
+    use crate::esr_test_util::{StubServer, StubRequest, StubResponse};
+
+    fn commits(logins: &[Option<&str>]) -> Vec<Value> {
+        logins.iter()
+            .map(|login| json!({ "author": { "user": login.map(|login| json!({ "login": login })) } }))
+            .collect()
+    }
+
+    fn history(nodes: Vec<Value>, next: Option<&str>) -> Value {
+        json!({
+            "totalCount": 16,
+            "nodes": nodes,
+            "pageInfo": { "hasNextPage": next.is_some(), "endCursor": next },
+        })
+    }
+
+    // Shaped like api.github.com responses, with only the queried fields
+    fn repo(name: &str) -> Value {
+        match name {
+            "a" => json!({
+                "createdAt": "2018-01-01T00:00:00Z",
+                "pushedAt": "2021-02-01T00:00:00Z",
+                "watchers": { "totalCount": 5 },
+                "issues": { "nodes": [{ "number": 1, "createdAt": "2020-01-01T00:00:00Z", "closedAt": "2020-02-01T00:00:00Z" }] },
+                "closedPullRequests": { "nodes": [{ "number": 7, "createdAt": "2021-01-01T00:00:00Z", "closedAt": "2021-01-05T00:00:00Z" }] },
+                "pullRequests": {
+                    "nodes": [{ "number": 8, "mergedAt": null }, { "number": 7, "mergedAt": "2021-01-05T00:00:00Z" }],
+                    "pageInfo": { "hasNextPage": false, "endCursor": null },
+                },
+                "defaultBranchRef": { "target": {
+                    "history": history(commits(&[Some("alice"), Some("bob"), None, Some("alice"), Some("alice")]), Some("c1")),
+                } },
+            }),
+            // Empty
+            _ => json!({
+                "createdAt": "2020-01-01T00:00:00Z",
+                "pushedAt": null,
+                "watchers": { "totalCount": 0 },
+                "issues": { "nodes": [] },
+                "closedPullRequests": { "nodes": [] },
+                "pullRequests": { "nodes": [], "pageInfo": { "hasNextPage": false, "endCursor": null } },
+                "defaultBranchRef": null,
+            }),
+        }
+    }
+
+    fn respond(request: &StubRequest) -> StubResponse {
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        let query = body["query"].as_str().unwrap();
+
+        if query.contains("after: \"c1\"") {
+            let page = history(commits(&[Some("carol"), Some("alice"), Some("carol")]), None);
+            let data = json!({ "repository": { "defaultBranchRef": { "target": { "history": page } } } });
+            return StubResponse::json(200, &json!({ "data": data }).to_string());
+        }
+
+        let alias_re = Regex::new(r#"(r\d+): repository\(owner: "o", name: "(\w+)"\)"#).unwrap();
+        let (mut data, mut errors) = (serde_json::Map::new(), Vec::new());
+        for caps in alias_re.captures_iter(query) {
+            let (alias, name) = (&caps[1], &caps[2]);
+            if name == "missing" {
+                data.insert(alias.into(), Value::Null);
+                errors.push(json!({
+                    "type": "NOT_FOUND",
+                    "path": [alias],
+                    "message": "Could not resolve to a Repository with the name 'o/missing'.",
+                }));
+            } else {
+                data.insert(alias.into(), repo(name));
+            }
+        }
+
+        StubResponse::json(200, &json!({ "data": data, "errors": errors }).to_string())
+    }
+
+    #[test]
+    fn batched_repos() {
+        let server = StubServer::start(respond);
+        let settings = GqlSettings {
+            endpoint: format!("{}/graphql", server.url()),
+            batch_size: 2,
+            pull_requests_pages: 1,
+            history_pages: 3,
+        };
+
+        let fetch = |name: &str| fetch_repo_with(format!("o/{}", name), &settings, "TKN".into());
+        let (a, b, missing) = smol::block_on(future::join3(fetch("a"), fetch("b"), fetch("missing")));
+
+        // alice: 3+1, carol: 2, bob: 1, of 8 fetched commits, scaled to 16
+        let a = serde_json::to_value(a.unwrap()).unwrap();
+        assert_eq!(a["general_info"]["subscribers_count"], 5);
+        assert_eq!(a["top_100_contributors"], json!([{ "contributions": 8 }, { "contributions": 4 }, { "contributions": 2 }]));
+        // The PR was created after the issue
+        assert_eq!(a["last_100_closed_issues"], json!([{ "closed_at": "2021-01-05T00:00:00Z", "number": 7 }]));
+        assert_eq!(a["last_100_pull_requests"].as_array().unwrap().len(), 2);
+
+        let b = serde_json::to_value(b.unwrap()).unwrap();
+        assert_eq!(b["general_info"]["pushed_at"], "2020-01-01T00:00:00Z");
+        assert_eq!(b["top_100_contributors"], json!([]));
+        assert_eq!(b["last_100_closed_issues"], json!([]));
+
+        match missing {
+            Err(EsrError::NotFound(ref url, Some(_))) => assert_eq!(url, "https://github.com/o/missing"),
+            res => panic!("Expected NotFound, got {:?}", res.map(|_| ())),
+        }
+
+        // Two batches of up to 2 repos, and a history page of "o/a"
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| r.method == "POST" && r.path == "/graphql"));
+        assert!(requests.iter().all(|r| r.authorization.as_deref() == Some("bearer TKN")));
+
+        let mut batch_sizes: Vec<_> = requests.iter()
+            .map(|r| r.body.matches(": repository(").count())
+            .filter(|&aliases| aliases > 0)
+            .collect();
+        batch_sizes.sort_unstable();
+        assert_eq!(batch_sizes, [1, 2]);
+    }
+
+    #[test]
+    fn batches_per_token() {
+        let server = StubServer::start(respond);
+        let settings = GqlSettings {
+            endpoint: format!("{}/graphql", server.url()),
+            batch_size: 10,
+            pull_requests_pages: 1,
+            history_pages: 1,
+        };
+
+        let fetch = |name: &str, token: &str| fetch_repo_with(format!("o/{}", name), &settings, token.into());
+        let (a, b, c) = smol::block_on(future::join3(fetch("a", "TKN1"), fetch("b", "TKN2"), fetch("c", "TKN1")));
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+
+        // Repos are only batched with those queried with the same token
+        let mut batches: Vec<_> = server.requests().iter()
+            .map(|r| {
+                let names: Vec<_> = ["a", "b", "c"].iter()
+                    .copied()
+                    .filter(|name| r.body.contains(&format!(r#"name: \"{}\""#, name)))
+                    .collect();
+                (r.authorization.clone().unwrap_or_default(), names)
+            })
+            .collect();
+        batches.sort();
+        assert_eq!(batches, [("bearer TKN1".to_string(), vec!["a", "c"]), ("bearer TKN2".to_string(), vec!["b"])]);
+    }
+}
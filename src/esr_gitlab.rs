@@ -0,0 +1,290 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use async_trait::async_trait;
+
+use crate::esr_from::EsrFrom;
+use crate::esr_util;
+use crate::esr_retry;
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_repo::{RepoProvider, RepoInfo, RepoGeneralInfo, PullRequestInfo, IssueInfo, ContributorInfo};
+
+pub const DEFAULT_GITLAB_URL: &str = "https://gitlab.com";
+
+#[derive(Debug, Clone)]
+pub struct GitLabConfig {
+    // e.g. "https://gitlab.example.com", without a trailing slash
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Default for GitLabConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_GITLAB_URL, None)
+    }
+}
+
+impl GitLabConfig {
+    pub fn new(base_url: &str, token: Option<String>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').into(),
+            token,
+        }
+    }
+
+    pub fn get_base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn host(&self) -> &str {
+        esr_util::url_host(&self.base_url).unwrap_or("")
+    }
+}
+
+static GITLAB_CONFIG: OnceCell<GitLabConfig> = OnceCell::new();
+
+pub fn get_gitlab_config() -> &'static GitLabConfig {
+    GITLAB_CONFIG.get_or_init(GitLabConfig::default)
+}
+
+pub fn set_gitlab_config(config: GitLabConfig) -> Result<()> {
+    GITLAB_CONFIG.set(config)
+        .map_err(|_| EsrError::from("GitLab config already set"))
+}
+
+fn gitlab_re() -> &'static Result<Regex> {
+    lazy_static! {
+        static ref RE: Result<Regex> =
+            Ok(Regex::new(r"^(?:(https?)://([^/]+)/|git@([^:/]+):)([^:]+?)(?:\.git)?/?$")?);
+    }
+    &RE
+}
+
+// Get the API URL of a project (e.g. "https://gitlab.com/api/v4/projects/group%2Fproject")
+// from its repository URL. Projects on gitlab.com and the configured instance
+// are recognized. Nested groups are supported.
+pub fn gitlab_project(repo: &str) -> Option<String> {
+    // Drop "/-/tree/master" and alike
+    let repo = repo.split("/-/").next().unwrap_or(repo);
+
+    let cap = match *gitlab_re() {
+        Ok(ref re) => re.captures(repo)?,
+        Err(_) => return None,
+    };
+
+    let host = cap.get(2).or_else(|| cap.get(3))?.as_str();
+    let path = &cap[4];
+
+    let config = get_gitlab_config();
+    let base_url = match host {
+        h if h == config.host() => config.get_base_url(),
+        "gitlab.com" => DEFAULT_GITLAB_URL,
+        _ => return None,
+    };
+
+    // "group/project" at least
+    if !path.contains('/') {
+        return None;
+    }
+
+    Some(format!("{}/api/v4/projects/{}", base_url, path.replace('/', "%2F")))
+}
+
+// Only the configured instance gets the token
+fn url_with_token(url: String) -> String {
+    let config = get_gitlab_config();
+    match config.token {
+        Some(ref token) if url.starts_with(config.get_base_url()) => {
+            let sep = if url.contains('?') { "&" } else { "?" };
+            format!("{}{}private_token={}", url, sep, token)
+        },
+        _ => url,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MergeRequestInfo {
+    merged_at: Option<String>,
+    iid: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabIssueInfo {
+    closed_at: Option<String>,
+    iid: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabContributorInfo {
+    commits: usize,
+}
+
+// =================
+
+type ProjectMergeRequests = Vec<MergeRequestInfo>;
+
+impl EsrFrom for ProjectMergeRequests {
+    fn url_from_id(id: &str) -> String {
+        url_with_token(format!("{}/merge_requests?state=all&per_page=100", id))
+    }
+}
+
+type ProjectClosedIssues = Vec<GitLabIssueInfo>;
+
+impl EsrFrom for ProjectClosedIssues {
+    fn url_from_id(id: &str) -> String {
+        url_with_token(format!("{}/issues?state=closed&order_by=updated_at&per_page=1", id))
+    }
+}
+
+type ProjectContributors = Vec<GitLabContributorInfo>;
+
+impl EsrFrom for ProjectContributors {
+    fn url_from_id(id: &str) -> String {
+        url_with_token(format!("{}/repository/contributors?order_by=commits&sort=desc&per_page=100", id))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ProjectGeneralInfo {
+    star_count: usize,
+    created_at: String,
+    last_activity_at: String,
+}
+
+impl EsrFrom for ProjectGeneralInfo {
+    fn url_from_id(id: &str) -> String {
+        url_with_token(id.into())
+    }
+}
+
+// =================
+
+// Fetch a GitLab project's info, mapped onto the normalized repo info.
+// Stars stand in for subscribers, and the last activity for the last push.
+async fn repo_info_from_project(project: String) -> Result<RepoInfo> {
+    // merge requests are slow, so we spawn them first
+    let merge_requests_fut = esr_retry::spawn(ProjectMergeRequests::from_id_owned(project.clone()));
+    let closed_issues_fut = esr_retry::spawn(ProjectClosedIssues::from_id_owned(project.clone()));
+    let contributors_fut = esr_retry::spawn(ProjectContributors::from_id_owned(project.clone()));
+    let general_info_fut = esr_retry::spawn(ProjectGeneralInfo::from_id_owned(project));
+
+    let general_info = general_info_fut.await?;
+
+    // Contributors are not sorted by commits on older GitLab versions
+    let mut contributors = contributors_fut.await?;
+    contributors.sort_by(|a, b| b.commits.cmp(&a.commits));
+
+    let convert_date = |date: &Option<String>| date.as_ref().map(|d| esr_util::to_iso8601(d));
+
+    let general_info = RepoGeneralInfo {
+        subscribers_count: general_info.star_count,
+        created_at: esr_util::to_iso8601(&general_info.created_at),
+        pushed_at: esr_util::to_iso8601(&general_info.last_activity_at),
+    };
+
+    let closed_issues = closed_issues_fut.await?
+        .iter()
+        .map(|issue| IssueInfo { closed_at: convert_date(&issue.closed_at), number: issue.iid })
+        .collect();
+
+    let merge_requests = merge_requests_fut.await?
+        .iter()
+        .map(|mr| PullRequestInfo { merged_at: convert_date(&mr.merged_at), number: mr.iid })
+        .collect();
+
+    let contributors = contributors
+        .iter()
+        .map(|c| ContributorInfo { contributions: c.commits })
+        .collect();
+
+    Ok(RepoInfo::new(general_info, closed_issues, merge_requests, contributors))
+}
+
+pub struct GitLabProvider;
+
+#[async_trait]
+impl RepoProvider for GitLabProvider {
+    fn name(&self) -> &str {
+        "GitLab"
+    }
+
+    fn detect(&self, repo: &str) -> Option<String> {
+        gitlab_project(repo)
+    }
+
+    async fn fetch(&self, id: String) -> Result<RepoInfo> {
+        repo_info_from_project(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::repo_info_from_project;
+    use crate::esr_test_util::{StubServer, StubResponse};
+
+    #[test]
+    fn gitlab_project_mapping() {
+        let server = StubServer::start(|request| {
+            let path = request.path.split('?').next().unwrap_or_default();
+            match path {
+                "/api/v4/projects/group%2Fproject" => StubResponse::json(200, r#"{
+                    "star_count": 12,
+                    "created_at": "2019-05-01T12:00:00.000+02:00",
+                    "last_activity_at": "2021-03-01T22:00:00.123-05:00"
+                }"#),
+                "/api/v4/projects/group%2Fproject/merge_requests" => StubResponse::json(200, r#"[
+                    {"iid": 7, "merged_at": null},
+                    {"iid": 6, "merged_at": "2021-02-01T10:00:00.000Z"}
+                ]"#),
+                "/api/v4/projects/group%2Fproject/issues" => StubResponse::json(200, r#"[
+                    {"iid": 3, "closed_at": "2021-01-15T08:30:00.000+01:00"}
+                ]"#),
+                // Not sorted by older GitLab versions
+                "/api/v4/projects/group%2Fproject/repository/contributors" => StubResponse::json(200, r#"[
+                    {"commits": 4}, {"commits": 40}, {"commits": 9}
+                ]"#),
+                _ => StubResponse::json(404, r#"{"message": "404 Not Found"}"#),
+            }
+        });
+
+        let project = format!("{}/api/v4/projects/group%2Fproject", server.url());
+        let repo_info = smol::block_on(repo_info_from_project(project)).expect("Failed to get project");
+        let info = serde_json::to_value(&repo_info).expect("Failed to serialize repo info");
+
+        assert_eq!(info["general_info"]["subscribers_count"], 12);
+        assert_eq!(info["general_info"]["created_at"], "2019-05-01T10:00:00Z");
+        assert_eq!(info["general_info"]["pushed_at"], "2021-03-02T03:00:00Z");
+
+        assert_eq!(info["last_100_pull_requests"][0]["number"], 7);
+        assert!(info["last_100_pull_requests"][0]["merged_at"].is_null());
+        assert_eq!(info["last_100_pull_requests"][1]["merged_at"], "2021-02-01T10:00:00Z");
+
+        assert_eq!(info["last_100_closed_issues"][0]["number"], 3);
+        assert_eq!(info["last_100_closed_issues"][0]["closed_at"], "2021-01-15T07:30:00Z");
+
+        let contributions: Vec<_> = info["top_100_contributors"].as_array().expect("No contributors")
+            .iter()
+            .map(|c| c["contributions"].as_u64().unwrap_or(0))
+            .collect();
+        assert_eq!(contributions, vec![40, 9, 4]);
+
+        // No token for hosts other than the configured instance
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().all(|request| request.method == "GET" && request.body.is_empty()));
+        assert!(requests.iter().all(|request| request.authorization.is_none() && !request.path.contains("private_token")));
+    }
+}
@@ -0,0 +1,344 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::fs;
+
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use once_cell::sync::OnceCell;
+
+use crate::esr_util;
+use crate::esr_crate::CrateInfoWithScore;
+use crate::esr_repo::RepoInfoWithScore;
+use crate::esr_score::{Scores, ScoreRow};
+use crate::esr_errors::{Result, EsrError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        name TEXT NOT NULL,
+        recorded_at TEXT NOT NULL,
+        weights_profile TEXT NOT NULL,
+        positive REAL NOT NULL,
+        negative REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_name ON snapshots (name, recorded_at);
+    CREATE TABLE IF NOT EXISTS factors (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots (id),
+        position INTEGER NOT NULL,
+        factor TEXT NOT NULL,
+        value REAL NOT NULL,
+        exponent REAL NOT NULL,
+        weight REAL NOT NULL,
+        contribution REAL NOT NULL,
+        available INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS factors_snapshot ON factors (snapshot_id);
+";
+
+static RECORD: OnceCell<bool> = OnceCell::new();
+
+pub fn is_recording() -> bool {
+    *RECORD.get().unwrap_or(&false)
+}
+
+pub fn set_recording(record: bool) -> Result<()> {
+    RECORD.set(record)
+        .map_err(|_| EsrError::from("Recording already set"))
+}
+
+// e.g. `~/.local/share/cargo-esr/history.sqlite`
+pub fn history_db_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cargo-esr").join("history.sqlite"))
+}
+
+fn get_db() -> Result<&'static Mutex<Connection>> {
+    static DB: OnceCell<Mutex<Connection>> = OnceCell::new();
+    let init = || -> Result<_> {
+        let path = history_db_path().ok_or("Failed to get data dir")?;
+        fs::create_dir_all(path.parent().ok_or("Impossible")?)?;
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Mutex::new(conn))
+    };
+    DB.get_or_try_init(init)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Snapshot {
+    // "crate" or "repo"
+    kind: &'static str,
+    name: String,
+    recorded_at: String,
+    weights_profile: String,
+    positive: f64,
+    negative: f64,
+    factors: Vec<ScoreRow>,
+}
+
+impl Snapshot {
+    fn new(kind: &'static str, name: &str, weights_profile: &str, (positive, negative): (f64, f64), table: &[ScoreRow]) -> Self {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        Self {
+            kind,
+            name: name.into(),
+            recorded_at: esr_util::timestamp_to_iso8601(now),
+            weights_profile: weights_profile.into(),
+            positive,
+            negative,
+            factors: table.to_vec(),
+        }
+    }
+
+    fn from_crate_score(name: &str, cr_score: &CrateInfoWithScore) -> Self {
+        Self::new("crate", name, cr_score.get_weights_profile(), cr_score.get_score_tuple(), cr_score.get_score_table())
+    }
+
+    fn from_repo_score(name: &str, repo_score: &RepoInfoWithScore) -> Self {
+        Self::new("repo", name, repo_score.get_weights_profile(), repo_score.get_score_tuple(), repo_score.get_score_table())
+    }
+
+    pub fn get_kind(&self) -> &str {
+        self.kind
+    }
+
+    pub fn get_recorded_at(&self) -> &str {
+        &self.recorded_at
+    }
+
+    pub fn get_total(&self) -> f64 {
+        self.positive + self.negative
+    }
+
+    pub fn get_factors(&self) -> &[ScoreRow] {
+        &self.factors
+    }
+
+    fn insert(&self, conn: &mut Connection) -> Result<()> {
+        let tx = conn.transaction()?;
+
+        tx.execute("INSERT INTO snapshots (kind, name, recorded_at, weights_profile, positive, negative)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                   params![self.kind, self.name, self.recorded_at, self.weights_profile, self.positive, self.negative])?;
+        let snapshot_id = tx.last_insert_rowid();
+
+        for (position, row) in self.factors.iter().enumerate() {
+            tx.execute("INSERT INTO factors (snapshot_id, position, factor, value, exponent, weight, contribution, available)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                       params![snapshot_id, position as i64, row.factor, row.value, row.exponent,
+                               row.weight, row.contribution, row.available])?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// Store the crate and repo scores of `name`
+pub async fn record(name: &str, scores: &Scores) -> Result<()> {
+    let snapshots: Vec<_> = scores.get_crate_score()
+        .map(|cr_score| Snapshot::from_crate_score(name, cr_score))
+        .into_iter()
+        .chain(scores.get_repo_score().map(|repo_score| Snapshot::from_repo_score(name, repo_score)))
+        .collect();
+
+    smol::unblock(move || {
+        let mut conn = get_db()?.lock().map_err(|_| "History DB lock poisoned")?;
+        for snapshot in &snapshots {
+            snapshot.insert(&mut conn)?;
+        }
+        Ok(())
+    }).await
+}
+
+// All snapshots of `name`, oldest first
+pub fn history(name: &str) -> Result<Vec<Snapshot>> {
+    let conn = get_db()?.lock().map_err(|_| "History DB lock poisoned")?;
+    history_from(&conn, name)
+}
+
+fn history_from(conn: &Connection, name: &str) -> Result<Vec<Snapshot>> {
+    let mut snapshots_stmt = conn.prepare(
+        "SELECT id, kind, recorded_at, weights_profile, positive, negative
+         FROM snapshots WHERE name = ?1 ORDER BY recorded_at, id")?;
+    let mut factors_stmt = conn.prepare(
+        "SELECT factor, value, exponent, weight, contribution, available
+         FROM factors WHERE snapshot_id = ?1 ORDER BY position")?;
+
+    let rows = snapshots_stmt.query_map(params![name], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+    })?;
+
+    let mut snapshots = Vec::new();
+    for row in rows {
+        let (id, kind, recorded_at, weights_profile, positive, negative) = row?;
+
+        let factors = factors_stmt.query_map(params![id], |row| {
+            Ok(ScoreRow {
+                factor: row.get(0)?,
+                value: row.get(1)?,
+                exponent: row.get(2)?,
+                weight: row.get(3)?,
+                contribution: row.get(4)?,
+                available: row.get(5)?,
+                count: false,
+            })
+        })?.collect::<rusqlite::Result<_>>()?;
+
+        snapshots.push(Snapshot {
+            kind: if kind == "repo" { "repo" } else { "crate" },
+            name: name.into(),
+            recorded_at,
+            weights_profile,
+            positive,
+            negative,
+            factors,
+        });
+    }
+
+    Ok(snapshots)
+}
+
+// Values of a factor (or the total) over time
+#[derive(Serialize, Debug, Clone)]
+pub struct Trend {
+    pub label: String,
+    pub values: Vec<f64>,
+    // False for factors with negative weights
+    pub higher_is_better: bool,
+}
+
+impl Trend {
+    // From the oldest value to the latest
+    pub fn change(&self) -> f64 {
+        match (self.values.first(), self.values.last()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0.0,
+        }
+    }
+}
+
+// Trends of the total and each factor in `snapshots` of `kind`, oldest values
+// first. Unavailable values are skipped.
+pub fn trends(snapshots: &[Snapshot], kind: &str) -> Vec<Trend> {
+    let snapshots: Vec<_> = snapshots.iter().filter(|s| s.kind == kind).collect();
+
+    let total = Trend {
+        label: "total".into(),
+        values: snapshots.iter().map(|s| s.get_total()).collect(),
+        higher_is_better: true,
+    };
+
+    // Factors of the latest snapshot, as weights may have changed
+    let factors = snapshots.last().map(|s| s.get_factors()).unwrap_or(&[]);
+
+    let factor_trends = factors.iter().map(|latest| Trend {
+        label: latest.factor.clone(),
+        values: snapshots.iter()
+            .filter_map(|s| s.factors.iter().find(|row| row.factor == latest.factor && row.available))
+            .map(|row| row.value)
+            .collect(),
+        higher_is_better: latest.weight >= 0.0,
+    });
+
+    Some(total).into_iter()
+        .chain(factor_trends)
+        .filter(|trend| !trend.values.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(factor: &str, value: f64, weight: f64, available: bool) -> ScoreRow {
+        ScoreRow {
+            factor: factor.into(),
+            value,
+            exponent: 1.0,
+            weight,
+            contribution: value * weight,
+            available,
+            count: false,
+        }
+    }
+
+    fn snapshot(kind: &'static str, name: &str, recorded_at: &str, factors: Vec<ScoreRow>) -> Snapshot {
+        let positive = factors.iter().map(|r| r.contribution).filter(|&c| c > 0.0).sum();
+        let negative = factors.iter().map(|r| r.contribution).filter(|&c| c < 0.0).sum();
+        Snapshot {
+            kind,
+            name: name.into(),
+            recorded_at: recorded_at.into(),
+            weights_profile: "default".into(),
+            positive,
+            negative,
+            factors,
+        }
+    }
+
+    #[test]
+    fn record_and_read_back_trends() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+
+        // Recorded out of order, with other snapshots in between
+        let newer = snapshot("crate", "foo", "2021-02-01T00:00:00Z", vec![
+            row("dependants", 12.0, 0.5, true),
+            row("months_since_last_release", 1.0, -2.0, true),
+            row("recent_downloads", 0.0, 0.0, false),
+        ]);
+        let older = snapshot("crate", "foo", "2021-01-01T00:00:00Z", vec![
+            row("dependants", 10.0, 0.5, true),
+            row("months_since_last_release", 3.0, -2.0, true),
+            row("recent_downloads", 500.0, 0.0, true),
+        ]);
+        let other = snapshot("crate", "bar", "2021-01-15T00:00:00Z", vec![row("dependants", 99.0, 0.5, true)]);
+        let repo = snapshot("repo", "foo", "2021-01-20T00:00:00Z", vec![row("subscribers", 4.0, 8.0, true)]);
+        for s in &[&newer, &other, &repo, &older] {
+            s.insert(&mut conn).unwrap();
+        }
+
+        let snapshots = history_from(&conn, "foo").unwrap();
+        let recorded: Vec<_> = snapshots.iter().map(|s| (s.get_kind(), s.get_recorded_at())).collect();
+        assert_eq!(recorded, [("crate", "2021-01-01T00:00:00Z"), ("repo", "2021-01-20T00:00:00Z"),
+                              ("crate", "2021-02-01T00:00:00Z")]);
+        let factors: Vec<_> = snapshots[2].get_factors().iter().map(|r| (&*r.factor, r.value, r.available)).collect();
+        assert_eq!(factors, [("dependants", 12.0, true), ("months_since_last_release", 1.0, true),
+                             ("recent_downloads", 0.0, false)]);
+        assert!(history_from(&conn, "missing").unwrap().is_empty());
+
+        let crate_trends = trends(&snapshots, "crate");
+        let changes: Vec<_> = crate_trends.iter()
+            .map(|t| (&*t.label, t.values.clone(), t.change(), t.higher_is_better))
+            .collect();
+        assert_eq!(changes, [
+            ("total", vec![-1.0, 4.0], 5.0, true),
+            ("dependants", vec![10.0, 12.0], 2.0, true),
+            ("months_since_last_release", vec![3.0, 1.0], -2.0, false),
+            // The unavailable value is skipped
+            ("recent_downloads", vec![500.0], 0.0, true),
+        ]);
+
+        let repo_trends = trends(&snapshots, "repo");
+        assert_eq!(repo_trends.iter().map(|t| (&*t.label, t.change())).collect::<Vec<_>>(),
+                   [("total", 0.0), ("subscribers", 0.0)]);
+    }
+}
@@ -0,0 +1,664 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use crates_index::{Index, Crate};
+use serde::{Deserialize, Serialize};
+use once_cell::sync::OnceCell;
+use futures::stream::{self, StreamExt};
+
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_from::{Meta, EsrFrom};
+use crate::esr_cache;
+use crate::esr_printer::EsrPrinter;
+
+// Reverse dependencies pages fetched at the same time. Popular crates have
+// hundreds of pages, and the API is rate-limited per client.
+const REV_DEPS_CONCURRENT_PAGES: usize = 2;
+
+pub const DEFAULT_SPARSE_INDEX_URL: &str = "https://index.crates.io/";
+
+static SPARSE_INDEX_URL: OnceCell<String> = OnceCell::new();
+
+// The sparse index to use instead of the git index, if any
+pub fn get_sparse_index_url() -> Option<&'static str> {
+    SPARSE_INDEX_URL.get().map(|url| url.as_str())
+}
+
+// e.g. "sparse+https://index.crates.io/" or "http://localhost:8000/index"
+pub fn set_sparse_index_url(url: &str) -> Result<()> {
+    let url = url.trim_start_matches("sparse+").trim_end_matches('/').to_string() + "/";
+    SPARSE_INDEX_URL.set(url)
+        .map_err(|_| EsrError::from("Sparse index URL already set"))
+}
+
+pub fn is_sparse() -> bool {
+    get_sparse_index_url().is_some()
+}
+
+static GIT_INDEX_URL: OnceCell<String> = OnceCell::new();
+
+// Use the git index at `url` (e.g. a private registry's) instead of crates.io's
+pub fn set_git_index_url(url: &str) -> Result<()> {
+    GIT_INDEX_URL.set(url.into())
+        .map_err(|_| EsrError::from("Git index URL already set"))
+}
+
+pub(crate) fn get_index() -> Result<&'static Index> {
+        static INDEX: OnceCell<std::result::Result<Index, String>> = OnceCell::new();
+        let init = || {
+            let index = match GIT_INDEX_URL.get() {
+                Some(url) => Index::from_url(url).map_err(|e| e.to_string())?,
+                None => Index::new_cargo_default(),
+            };
+
+            // Use whatever we have locally
+            if esr_cache::is_offline() {
+                return match index.exists() {
+                    true => Ok(index),
+                    false => Err("Offline mode requires an existing local crates index".into()),
+                };
+            }
+
+            EsrPrinter::crate_index_init().eprintln();
+            index.retrieve_or_update()
+                .map_err(|e| e.to_string())
+                .map(|_| index)
+        };
+        INDEX.get_or_init(init)
+            .as_ref()
+            .map_err(|e| EsrError::from(&**e))
+}
+
+// Path of a crate's file in the index (e.g. "se/rd/serde", "3/s/syn")
+fn index_file_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+// A version entry in the index. Only the parts we use.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IndexVersion {
+    name: String,
+    vers: String,
+    yanked: bool,
+}
+
+impl IndexVersion {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_version(&self) -> &str {
+        &self.vers
+    }
+
+    pub fn is_yanked(&self) -> bool {
+        self.yanked
+    }
+}
+
+// A crate's file in the sparse index. One JSON version entry per line.
+type SparseIndexFile = Vec<IndexVersion>;
+
+impl EsrFrom for SparseIndexFile {
+    fn url_from_id(id: &str) -> String {
+        get_sparse_index_url().unwrap_or(DEFAULT_SPARSE_INDEX_URL).to_string() + &index_file_path(id)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut versions = Vec::new();
+        for line in bytes.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            versions.push(serde_json::from_slice(line)?);
+        }
+        Ok(versions)
+    }
+}
+
+// `config.json` at the root of the index
+#[derive(Deserialize, Debug)]
+struct IndexConfig {
+    api: Option<String>,
+}
+
+impl EsrFrom for IndexConfig {
+    fn url_from_id(id: &str) -> String {
+        String::from(id) + "config.json"
+    }
+}
+
+// The API base URL advertised in the index's `config.json`, if any
+pub async fn index_api() -> Result<Option<String>> {
+    match get_sparse_index_url() {
+        Some(url) => Ok(IndexConfig::from_id(url).await?.api),
+        None => smol::unblock(|| {
+            let config_path = get_index()?.path().join("config.json");
+            let config: IndexConfig = serde_json::from_slice(&fs::read(config_path)?)?;
+            Ok(config.api)
+        }).await,
+    }
+}
+
+// Versions of a crate, oldest first, from whichever index is in use
+pub async fn index_versions(name: String) -> Result<Vec<IndexVersion>> {
+    let versions = match get_sparse_index_url() {
+        Some(url) => {
+            log::debug!("Getting {} from sparse index {}", name, url);
+            SparseIndexFile::from_id(&name).await?
+        },
+        None => smol::unblock(move || {
+            let cr = match get_index()?.crate_(&name) {
+                Some(cr) => cr,
+                None => return Ok(Vec::new()),
+            };
+            Ok::<_, EsrError>(cr.versions()
+                .iter()
+                .map(|ver| IndexVersion {
+                    name: cr.name().into(),
+                    vers: ver.version().into(),
+                    yanked: ver.is_yanked(),
+                })
+                .collect())
+        }).await?,
+    };
+    Ok(versions)
+}
+
+// A crate depending on another crate, in its latest (non-yanked) version
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Dependant {
+    crate_name: String,
+    default_features: bool,
+    optional: bool,
+    req: String, // version required
+}
+
+impl Dependant {
+    pub fn get_crate_name(&self) -> &str {
+        &self.crate_name
+    }
+
+    pub fn has_default_features(&self) -> bool {
+        self.default_features
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    pub fn get_req(&self) -> &str {
+        &self.req
+    }
+}
+
+// Dependants of all crates in the index. Built once per index commit, and
+// persisted in the cache dir.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ReverseDeps {
+    index_commit: Option<String>,
+    // crate => dependants
+    map: HashMap<String, Vec<Dependant>>,
+}
+
+// The commit the index is at. crates-index doesn't expose it, so we ask
+// git2 (which it updates the index with) directly.
+fn index_commit(index_path: &Path) -> Option<String> {
+    let repo = git2::Repository::open(index_path).ok()?;
+    ["FETCH_HEAD", "origin/HEAD", "HEAD"].iter()
+        .filter_map(|rev| repo.revparse_single(rev).ok())
+        .filter_map(|obj| obj.peel_to_commit().ok())
+        .map(|commit| commit.id().to_string())
+        .next()
+}
+
+// e.g. `~/.cache/cargo-esr/index`
+fn reverse_deps_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cargo-esr").join("index"))
+}
+
+impl ReverseDeps {
+    pub fn from_index(index: &Index) -> Self {
+        Self::from_crates(index.crates(), index_commit(index.path()))
+    }
+
+    fn from_crates(crates: impl Iterator<Item=Crate>, index_commit: Option<String>) -> Self {
+        let mut map: HashMap<String, Vec<Dependant>> = HashMap::with_capacity(64 * 1024);
+
+        for cr in crates {
+            let latest_version = cr.latest_version();
+            if latest_version.is_yanked() {
+                continue;
+            }
+
+            // A crate depending on another in multiple ways (e.g. normal and dev) counts once
+            let mut seen = HashSet::new();
+            for dep in latest_version.dependencies() {
+                if !seen.insert(dep.crate_name()) {
+                    continue;
+                }
+
+                map.entry(dep.crate_name().into())
+                    .or_insert_with(Vec::new)
+                    .push(Dependant {
+                        crate_name: cr.name().into(),
+                        default_features: dep.has_default_features() && !dep.is_optional(),
+                        optional: dep.is_optional(),
+                        req: dep.requirement().into(),
+                    });
+            }
+        }
+
+        Self {
+            index_commit,
+            map,
+        }
+    }
+
+    fn persisted_path(dir: &Path, commit: &str) -> PathBuf {
+        dir.join(format!("reverse-deps-{}.json", commit))
+    }
+
+    fn load(dir: &Path, commit: &str) -> Option<Self> {
+        let data = fs::read(Self::persisted_path(dir, commit)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    // Only the map of the current commit is kept
+    fn persist(&self, dir: &Path) -> Result<()> {
+        let commit = match self.index_commit {
+            Some(ref commit) => commit,
+            None => return Ok(()),
+        };
+
+        // Write to a temp file first, so concurrent runs never load partial maps
+        fs::create_dir_all(dir)?;
+        let path = Self::persisted_path(dir, commit);
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, &path)?;
+
+        // Maps of old commits. Other runs' temp files are left alone.
+        for entry in fs::read_dir(dir)? {
+            let entry_path = entry?.path();
+            if entry_path != path && entry_path.extension().map(|ext| ext == "json").unwrap_or(false) {
+                fs::remove_file(entry_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Load the map persisted for the current index commit, or build it (and persist it)
+    pub fn from_index_cached(index: &Index) -> Self {
+        let dir = reverse_deps_dir();
+
+        if let (Some(ref dir), Some(commit)) = (&dir, index_commit(index.path())) {
+            if let Some(reverse_deps) = Self::load(dir, &commit) {
+                log::debug!("Loaded reverse dependencies for index commit {}", commit);
+                return reverse_deps;
+            }
+        }
+
+        log::debug!("Building reverse dependencies from index");
+        let reverse_deps = Self::from_index(index);
+        let persisted = match dir {
+            Some(ref dir) => reverse_deps.persist(dir),
+            None => Err(EsrError::from("Failed to get cache dir")),
+        };
+        if let Err(e) = persisted {
+            log::debug!("Failed to persist reverse dependencies: {}", e);
+        }
+        reverse_deps
+    }
+
+    pub fn get_index_commit(&self) -> Option<&str> {
+        self.index_commit.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn dependants(&self, name: &str) -> &[Dependant] {
+        self.map.get(name).map(|d| d.as_slice()).unwrap_or(&[])
+    }
+
+    // All crates depending on `name`, directly or indirectly (breadth-first).
+    // Direct dependants are at depth 1. `None` for no depth limit.
+    pub fn transitive_dependants(&self, name: &str, max_depth: Option<usize>) -> HashSet<&str> {
+        let mut ret = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((name, 0));
+
+        while let Some((curr, depth)) = queue.pop_front() {
+            if max_depth.map(|max| depth >= max).unwrap_or(false) {
+                continue;
+            }
+
+            for dependant in self.dependants(curr) {
+                let dependant_name = dependant.get_crate_name();
+                if dependant_name != name && ret.insert(dependant_name) {
+                    queue.push_back((dependant_name, depth + 1));
+                }
+            }
+        }
+
+        ret
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ApiReverseDependency {
+    version_id: u64,
+    req: String,
+    optional: bool,
+    default_features: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ApiDependantVersion {
+    id: u64,
+    #[serde(rename = "crate")]
+    crate_name: String,
+}
+
+// A page of `/api/v1/crates/:id/reverse_dependencies`
+#[derive(Deserialize, Debug)]
+struct ApiReverseDependencies {
+    dependencies: Vec<ApiReverseDependency>,
+    versions: Vec<ApiDependantVersion>,
+    meta: Meta,
+}
+
+impl EsrFrom for ApiReverseDependencies {}
+
+// The sparse index only has dependencies. So, dependants are fetched from the
+// API advertised in the index config (and cached like other API responses).
+pub async fn sparse_dependants(name: &str) -> Result<Vec<Dependant>> {
+    let api = index_api().await?
+        .ok_or("Sparse index has no API to get reverse dependencies from")?;
+
+    let url = format!("{}/api/v1/crates/{}/reverse_dependencies?per_page=100", api.trim_end_matches('/'), name);
+    let mut rev_deps = ApiReverseDependencies::from_url(&url).await?;
+
+    // per_page=100 is the maximum number allowed
+    let num_pages = (rev_deps.meta.total as f64 / 100.0).ceil() as usize;
+    let mut more_pages = stream::iter(2..=num_pages)
+        .map(|page| url.clone() + &*format!("&page={}", page))
+        .map(ApiReverseDependencies::from_url_owned)
+        .buffered(REV_DEPS_CONCURRENT_PAGES);
+
+    while let Some(page_res) = more_pages.next().await {
+        let page = page_res?;
+        rev_deps.dependencies.extend(page.dependencies);
+        rev_deps.versions.extend(page.versions);
+    }
+
+    let crate_names: HashMap<_, _> = rev_deps.versions
+        .iter()
+        .map(|ver| (ver.id, &*ver.crate_name))
+        .collect();
+
+    let mut seen = HashSet::new();
+    let dependants = rev_deps.dependencies
+        .iter()
+        .filter_map(|dep| crate_names.get(&dep.version_id).map(|&crate_name| (crate_name, dep)))
+        .filter(|(crate_name, _)| seen.insert(*crate_name))
+        .map(|(crate_name, dep)| Dependant {
+            crate_name: crate_name.into(),
+            default_features: dep.default_features && !dep.optional,
+            optional: dep.optional,
+            req: dep.req.clone(),
+        })
+        .collect();
+
+    Ok(dependants)
+}
+
+// Get the reverse dependencies map, built (or loaded) on first use
+pub fn get_reverse_deps() -> Result<&'static ReverseDeps> {
+    static REVERSE_DEPS: OnceCell<ReverseDeps> = OnceCell::new();
+    REVERSE_DEPS.get_or_try_init(|| Ok(ReverseDeps::from_index_cached(get_index()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::esr_test_util::{StubServer, StubResponse};
+
+    fn index_crate(versions: &[(&str, &str, bool, &str)]) -> Crate {
+        let lines: Vec<_> = versions.iter()
+            .map(|&(name, vers, yanked, deps)| {
+                format!(r#"{{"name":"{}","vers":"{}","deps":[{}],"cksum":"00","features":{{}},"yanked":{}}}"#,
+                        name, vers, deps, yanked)
+            })
+            .collect();
+        Crate::from_slice(lines.join("\n").as_bytes()).expect("Invalid index entry")
+    }
+
+    fn dep(name: &str, req: &str, kind: &str, optional: bool, package: Option<&str>) -> String {
+        let package = package.map(|p| format!(r#","package":"{}""#, p)).unwrap_or_default();
+        format!(r#"{{"name":"{}","req":"{}","features":[],"optional":{},"default_features":true,"target":null,"kind":"{}"{}}}"#,
+                name, req, optional, kind, package)
+    }
+
+    fn reverse_deps() -> ReverseDeps {
+        let crates = vec![
+            index_crate(&[("base", "1.0.0", false, "")]),
+            // Counted once, even if depended on in multiple ways
+            index_crate(&[("app", "0.1.0", false,
+                           &[dep("base", "^1", "normal", false, None), dep("base", "^1", "dev", false, None)].join(","))]),
+            index_crate(&[("plugin", "0.2.0", false, &dep("base", "^0.9", "normal", true, None))]),
+            // Renamed
+            index_crate(&[("renamed", "1.0.0", false, &dep("b", "^1", "normal", false, Some("base")))]),
+            // Only the latest version counts
+            index_crate(&[("dropped", "1.0.0", false, &dep("base", "^1", "normal", false, None)),
+                          ("dropped", "2.0.0", false, "")]),
+            index_crate(&[("yanked", "1.0.0", true, &dep("base", "^1", "normal", false, None))]),
+        ];
+        ReverseDeps::from_crates(crates.into_iter(), Some("0123abcd".into()))
+    }
+
+    #[test]
+    fn reverse_deps_from_crates() {
+        let reverse_deps = reverse_deps();
+        let dependants = reverse_deps.dependants("base");
+        let names: Vec<_> = dependants.iter().map(|d| d.get_crate_name()).collect();
+        assert_eq!(names, ["app", "plugin", "renamed"]);
+
+        assert!(dependants[0].has_default_features() && !dependants[0].is_optional());
+        assert!(!dependants[1].has_default_features() && dependants[1].is_optional());
+        assert_eq!(dependants[1].get_req(), "^0.9");
+        assert!(reverse_deps.dependants("app").is_empty());
+        assert_eq!(reverse_deps.get_index_commit(), Some("0123abcd"));
+    }
+
+    #[test]
+    fn reverse_deps_persisted_per_commit() {
+        let dir = std::env::temp_dir().join(format!("esr-index-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut reverse_deps = reverse_deps();
+        reverse_deps.persist(&dir).unwrap();
+        let loaded = ReverseDeps::load(&dir, "0123abcd").expect("Failed to load persisted map");
+        assert_eq!(loaded.get_index_commit(), Some("0123abcd"));
+        assert_eq!(loaded.dependants("base").len(), 3);
+        assert!(ReverseDeps::load(&dir, "4567cdef").is_none());
+
+        // Maps of old commits are removed, once the new one is in place
+        reverse_deps.index_commit = Some("4567cdef".into());
+        reverse_deps.persist(&dir).unwrap();
+        assert!(ReverseDeps::load(&dir, "0123abcd").is_none());
+        assert!(ReverseDeps::load(&dir, "4567cdef").is_some());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Nothing to key the map by
+        reverse_deps.index_commit = None;
+        reverse_deps.persist(&dir).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn graph(edges: &[(&str, &str)]) -> ReverseDeps {
+        let mut map: HashMap<String, Vec<Dependant>> = HashMap::new();
+        for &(dependency, dependant) in edges {
+            map.entry(dependency.into()).or_insert_with(Vec::new).push(Dependant {
+                crate_name: dependant.into(),
+                default_features: true,
+                optional: false,
+                req: "*".into(),
+            });
+        }
+        ReverseDeps { index_commit: None, map }
+    }
+
+    fn sorted(set: HashSet<&str>) -> Vec<&str> {
+        let mut ret: Vec<_> = set.into_iter().collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn transitive_dependants_walk() {
+        // core <- a <- b <- c <- d, with b and c also depending on core directly
+        let rd = graph(&[("core", "a"), ("core", "b"), ("core", "c"), ("a", "b"), ("b", "c"), ("c", "d"),
+                         ("other", "x")]);
+
+        // Reached in multiple ways, but listed once
+        assert_eq!(sorted(rd.transitive_dependants("core", None)), ["a", "b", "c", "d"]);
+        assert_eq!(sorted(rd.transitive_dependants("a", None)), ["b", "c", "d"]);
+        assert!(rd.transitive_dependants("d", None).is_empty());
+        assert!(rd.transitive_dependants("missing", None).is_empty());
+
+        // Depth is the shortest path, so c is a direct dependant of core
+        assert!(rd.transitive_dependants("core", Some(0)).is_empty());
+        assert_eq!(sorted(rd.transitive_dependants("core", Some(1))), ["a", "b", "c"]);
+        assert_eq!(sorted(rd.transitive_dependants("core", Some(2))), ["a", "b", "c", "d"]);
+        assert_eq!(sorted(rd.transitive_dependants("a", Some(1))), ["b"]);
+        assert_eq!(sorted(rd.transitive_dependants("a", Some(2))), ["b", "c"]);
+    }
+
+    #[test]
+    fn transitive_dependants_with_cycles() {
+        // Dev-dependencies make cycles possible (e.g. a crate's test helpers depending on it)
+        let rd = graph(&[("a", "b"), ("b", "c"), ("c", "a"), ("c", "d"), ("d", "d")]);
+
+        // A crate is never its own dependant
+        assert_eq!(sorted(rd.transitive_dependants("a", None)), ["b", "c", "d"]);
+        assert_eq!(sorted(rd.transitive_dependants("d", None)), Vec::<&str>::new());
+        assert_eq!(sorted(rd.transitive_dependants("c", Some(2))), ["a", "b", "d"]);
+    }
+
+    #[test]
+    fn index_commit_from_repo() {
+        let dir = std::env::temp_dir().join(format!("esr-index-commit-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(index_commit(&dir), None);
+
+        let git = |dir: &Path, args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C").arg(dir)
+                .args(&["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .expect("Failed to run git");
+            assert!(output.status.success(), "git {}", args.join(" "));
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        let upstream = dir.join("upstream");
+        let clone = dir.join("clone");
+        fs::create_dir_all(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet"]);
+        git(&upstream, &["commit", "--quiet", "--allow-empty", "-m", "index"]);
+        assert_eq!(index_commit(&upstream), Some(git(&upstream, &["rev-parse", "HEAD"])));
+
+        // Fetched, but not merged (like the index updated by crates-index)
+        git(&dir, &["clone", "--quiet", &upstream.to_string_lossy(), &clone.to_string_lossy()]);
+        git(&upstream, &["commit", "--quiet", "--allow-empty", "-m", "update"]);
+        git(&clone, &["fetch", "--quiet"]);
+        assert_ne!(git(&clone, &["rev-parse", "HEAD"]), git(&upstream, &["rev-parse", "HEAD"]));
+        assert_eq!(index_commit(&clone), Some(git(&upstream, &["rev-parse", "HEAD"])));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sparse_index_from_stub() {
+        let api_url = Arc::new(OnceCell::new());
+        let handler_api_url = api_url.clone();
+        let server = StubServer::start(move |request| {
+            let rev_deps_page = |page: usize| format!("/api/v1/crates/serde/reverse_dependencies?per_page=100{}",
+                                                      if page == 1 { "".into() } else { format!("&page={}", page) });
+            match &*request.path {
+                "/index/config.json" => {
+                    let api = handler_api_url.get().cloned().unwrap_or_default();
+                    StubResponse::json(200, &json!({ "dl": "https://dl.example/", "api": api }).to_string())
+                },
+                "/index/se/rd/serde" => StubResponse::json(200, concat!(
+                    r#"{"name":"serde","vers":"1.0.0","deps":[],"cksum":"00","features":{},"yanked":false}"#, "\n",
+                    r#"{"name":"serde","vers":"1.0.1","deps":[],"cksum":"00","features":{},"yanked":true}"#, "\n",
+                    r#"{"name":"serde","vers":"1.0.2","deps":[],"cksum":"00","features":{},"yanked":false}"#, "\n")),
+                "/index/3/s/syn" => StubResponse::json(200, concat!(
+                    r#"{"name":"syn","vers":"0.1.0","deps":[],"cksum":"00","features":{},"yanked":false}"#, "\n")),
+                path if path == rev_deps_page(1) => StubResponse::json(200, &json!({
+                    "dependencies": [
+                        { "version_id": 1, "req": "^1", "optional": false, "default_features": true },
+                        { "version_id": 2, "req": "^1.0.2", "optional": true, "default_features": true },
+                        // An older version of a crate already listed
+                        { "version_id": 3, "req": "^0.9", "optional": false, "default_features": true },
+                    ],
+                    "versions": [{ "id": 1, "crate": "a" }, { "id": 2, "crate": "b" }, { "id": 3, "crate": "a" }],
+                    "meta": { "total": 150 },
+                }).to_string()),
+                path if path == rev_deps_page(2) => StubResponse::json(200, &json!({
+                    "dependencies": [{ "version_id": 4, "req": "*", "optional": false, "default_features": false }],
+                    "versions": [{ "id": 4, "crate": "c" }],
+                    "meta": { "total": 150 },
+                }).to_string()),
+                _ => StubResponse::json(404, r#"{"errors":[{"detail":"Not Found"}]}"#),
+            }
+        });
+        api_url.set(server.url().to_string()).unwrap();
+        set_sparse_index_url(&format!("sparse+{}/index", server.url())).expect("Sparse index URL already set");
+        assert!(is_sparse());
+
+        smol::block_on(async {
+            assert_eq!(index_api().await.unwrap().as_deref(), Some(server.url()));
+
+            let versions = index_versions("serde".into()).await.unwrap();
+            let versions: Vec<_> = versions.iter().map(|v| (v.get_name(), v.get_version(), v.is_yanked())).collect();
+            assert_eq!(versions, [("serde", "1.0.0", false), ("serde", "1.0.1", true), ("serde", "1.0.2", false)]);
+            assert_eq!(index_versions("SYN".into()).await.unwrap()[0].get_version(), "0.1.0");
+            assert!(index_versions("missing".into()).await.is_err());
+
+            let dependants = sparse_dependants("serde").await.unwrap();
+            let dependants: Vec<_> = dependants.iter()
+                .map(|d| (d.get_crate_name(), d.get_req(), d.has_default_features(), d.is_optional()))
+                .collect();
+            assert_eq!(dependants, [("a", "^1", true, false), ("b", "^1.0.2", false, true), ("c", "*", false, false)]);
+        });
+
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert!(paths.contains(&"/index/3/s/syn".to_string()), "{:?}", paths);
+        // 150 dependants are 2 pages
+        assert!(paths.iter().all(|path| !path.contains("page=3")), "{:?}", paths);
+    }
+}
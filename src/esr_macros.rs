@@ -10,14 +10,35 @@
 */
 
 macro_rules! score_add {
-  ($table:ident, $score:ident, $count:expr, $weight:expr) => {
+  ($table:ident, $pos_score:ident, $neg_score:ident, $factors:expr, $unavailable:ident, $count:expr) => {
       {
-          let incr = ($count as f64) * $weight;
-          let count_str = format!("{: ^49}", stringify!($count).replace("self.", ""));
-          let count_mul_weight_num = format!("{: ^18}", format!("{:.3} * {:.3}", $count, $weight));
-          let incr_num_str = format!("{:0.3}", incr);
-          $table.push((count_str, count_mul_weight_num, incr_num_str));
-          $score += incr;
+          let factor = stringify!($count).replace("self.", "");
+          // Disabled factors are not added to the table
+          if let Some(fw) = $factors.get(&*factor).filter(|fw| fw.enabled) {
+              // Unavailable factors are listed, but contribute nothing
+              let available = !$unavailable.iter().any(|&u| u == factor);
+              // Factor values are never negative. But if one was, a
+              // fractional exponent would make its contribution NaN.
+              let value = match available {
+                  true => ($count as f64).max(0.0),
+                  false => 0.0,
+              };
+              let incr = value.powf(fw.exponent) * fw.weight;
+              $table.push($crate::esr_score::ScoreRow {
+                  factor,
+                  value,
+                  exponent: fw.exponent,
+                  weight: fw.weight,
+                  contribution: incr,
+                  available,
+                  count: $crate::esr_score::is_count($count),
+              });
+
+              match fw.weight < 0.0 {
+                  true => $neg_score += incr,
+                  false => $pos_score += incr,
+              }
+          }
       }
   }
 }
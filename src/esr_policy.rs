@@ -0,0 +1,473 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::path::Path;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::esr_errors::Result;
+use crate::esr_score::{Scores, ScoreRow};
+
+// `check` exit codes
+pub const EXIT_VIOLATIONS: i32 = 2;
+pub const EXIT_FETCH_FAILED: i32 = 3;
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    min_crate_score: Option<f64>,
+    min_repo_score: Option<f64>,
+    max_months_since_last_release: Option<f64>,
+    #[serde(default)]
+    forbid_empty_or_all_yanked: bool,
+    allowed_licenses: Option<Vec<String>>,
+    // Crates exempted from all checks
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Violation {
+    #[serde(rename = "crate")]
+    crate_name: String,
+    rule: &'static str,
+    message: String,
+    factor: Option<ScoreRow>,
+}
+
+impl Violation {
+    fn new(crate_name: &str, rule: &'static str, message: String, factor: Option<&ScoreRow>) -> Self {
+        Self {
+            crate_name: crate_name.into(),
+            rule,
+            message,
+            factor: factor.cloned(),
+        }
+    }
+
+    pub fn get_crate_name(&self) -> &str {
+        &self.crate_name
+    }
+
+    pub fn get_rule(&self) -> &str {
+        self.rule
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn get_factor(&self) -> Option<&ScoreRow> {
+        self.factor.as_ref()
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct PolicyReport {
+    checked: usize,
+    violations: Vec<Violation>,
+    // (crate, error)
+    failures: Vec<(String, String)>,
+}
+
+impl PolicyReport {
+    pub fn get_checked(&self) -> usize {
+        self.checked
+    }
+
+    pub fn get_violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn get_failures(&self) -> &[(String, String)] {
+        &self.failures
+    }
+
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty() && self.failures.is_empty()
+    }
+
+    // Failures take precedence, as unchecked crates may have violations too
+    pub fn exit_code(&self) -> i32 {
+        if !self.failures.is_empty() {
+            EXIT_FETCH_FAILED
+        } else if !self.violations.is_empty() {
+            EXIT_VIOLATIONS
+        } else {
+            0
+        }
+    }
+}
+
+// Evaluates an SPDX license expression against an allow-list, with AND
+// binding tighter than OR. `/` is the legacy crates.io spelling of OR.
+// None if the expression is malformed.
+struct LicenseExpr<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    allowed: &'a [String],
+}
+
+impl<'a> LicenseExpr<'a> {
+    fn eval(allowed: &'a [String], license: &'a str) -> Option<bool> {
+        let mut expr = Self { tokens: Self::tokenize(license), pos: 0, allowed };
+        let ret = expr.or_expr()?;
+        match expr.pos == expr.tokens.len() {
+            true => Some(ret),
+            false => None,
+        }
+    }
+
+    fn tokenize(license: &str) -> Vec<&str> {
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (idx, c) in license.char_indices() {
+            let is_punct = c == '(' || c == ')' || c == '/';
+            if is_punct || c.is_whitespace() {
+                if let Some(start) = start.take() {
+                    tokens.push(&license[start..idx]);
+                }
+                if is_punct {
+                    tokens.push(&license[idx..idx + 1]);
+                }
+            } else if start.is_none() {
+                start = Some(idx);
+            }
+        }
+        if let Some(start) = start {
+            tokens.push(&license[start..]);
+        }
+        tokens
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        match self.peek() {
+            Some(t) if t.eq_ignore_ascii_case(token) => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn or_expr(&mut self) -> Option<bool> {
+        let mut ret = self.and_expr()?;
+        while self.eat("OR") || self.eat("/") {
+            // Evaluated anyway, so malformed alternatives are caught
+            let alt = self.and_expr()?;
+            ret = ret || alt;
+        }
+        Some(ret)
+    }
+
+    fn and_expr(&mut self) -> Option<bool> {
+        let mut ret = self.atom()?;
+        while self.eat("AND") {
+            let other = self.atom()?;
+            ret = ret && other;
+        }
+        Some(ret)
+    }
+
+    fn atom(&mut self) -> Option<bool> {
+        if self.eat("(") {
+            let ret = self.or_expr()?;
+            return match self.eat(")") {
+                true => Some(ret),
+                false => None,
+            };
+        }
+
+        let is_operand = |t: &str| !["(", ")", "/", "OR", "AND", "WITH"].iter().any(|op| op.eq_ignore_ascii_case(t));
+        let id = self.peek().filter(|&t| is_operand(t))?;
+        self.pos += 1;
+
+        if !self.eat("WITH") {
+            return Some(self.is_allowed(id));
+        }
+
+        // Exceptions only grant extra permissions. So, allowing a license
+        // allows it with any exception.
+        let exception = self.peek().filter(|&t| is_operand(t))?;
+        self.pos += 1;
+        Some(self.is_allowed(id) || self.is_allowed(&format!("{} WITH {}", id, exception)))
+    }
+
+    fn is_allowed(&self, license: &str) -> bool {
+        self.allowed.iter().any(|a| {
+            a.split_whitespace().collect::<Vec<_>>().join(" ").eq_ignore_ascii_case(license)
+        })
+    }
+}
+
+fn find_row<'a>(table: &'a [ScoreRow], factor: &str) -> Option<&'a ScoreRow> {
+    table.iter().find(|row| row.factor == factor)
+}
+
+// The row that dragged the score down the most
+fn worst_row(table: &[ScoreRow]) -> Option<&ScoreRow> {
+    table.iter()
+        .filter(|row| row.contribution < 0.0)
+        .min_by(|a, b| a.contribution.partial_cmp(&b.contribution).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+impl Policy {
+    pub fn from_toml_str(toml_str: &str) -> Result<Self> {
+        Ok(toml::from_str(toml_str)?)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    // Checks if a license expression like "MIT OR Apache-2.0", "MIT/Apache-2.0"
+    // or "(MIT OR Apache-2.0) AND Unicode-DFS-2016" is satisfied by the allow-list.
+    // Malformed expressions are not.
+    fn license_allowed(allowed: &[String], license: &str) -> bool {
+        LicenseExpr::eval(allowed, license).unwrap_or(false)
+    }
+
+    // `crate_only` skips repo checks, as no repo scores were requested
+    pub fn check_scores(&self, id: &str, scores: &Scores, crate_only: bool) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if let Some(cr_score) = scores.get_crate_score() {
+            let cr_info = cr_score.get_info();
+            let table = cr_score.get_score_table();
+            let (pos, neg) = cr_score.get_score_tuple();
+            let score_info = cr_score.get_score_info();
+
+            if let Some(min) = self.min_crate_score {
+                if pos + neg < min {
+                    let msg = format!("crate score {:.3} is below the minimum {:.3}", pos + neg, min);
+                    violations.push(Violation::new(id, "min_crate_score", msg, worst_row(table)));
+                }
+            }
+
+            // Rules based on unavailable info (e.g. offline) are skipped
+            let max_months = self.max_months_since_last_release
+                .filter(|_| score_info.is_available("months_since_last_release"));
+            if let Some(max) = max_months {
+                let months = score_info.get_months_since_last_release();
+                if months > max {
+                    let msg = format!("last release was {:.1} months ago (maximum: {:.1})", months, max);
+                    let row = find_row(table, "months_since_last_release");
+                    violations.push(Violation::new(id, "max_months_since_last_release", msg, row));
+                }
+            }
+
+            if self.forbid_empty_or_all_yanked && cr_info.empty_or_all_yanked() {
+                let msg = "crate is empty or all its releases are yanked".to_string();
+                let row = find_row(table, "empty_or_all_yanked");
+                violations.push(Violation::new(id, "forbid_empty_or_all_yanked", msg, row));
+            }
+
+            let allowed_licenses = self.allowed_licenses
+                .as_ref()
+                .filter(|_| score_info.is_available("has_license"));
+            if let Some(allowed) = allowed_licenses {
+                match cr_info.get_license() {
+                    Some(license) if Self::license_allowed(allowed, license) => (),
+                    Some(license) => {
+                        let msg = format!("license \"{}\" is not allowed", license);
+                        violations.push(Violation::new(id, "allowed_licenses", msg, find_row(table, "has_license")));
+                    },
+                    None => {
+                        let msg = "crate has no license".to_string();
+                        violations.push(Violation::new(id, "allowed_licenses", msg, find_row(table, "has_license")));
+                    },
+                }
+            }
+        }
+
+        if let (false, Some(min)) = (crate_only, self.min_repo_score) {
+            match (scores.get_repo_score(), scores.get_repo_error()) {
+                (Some(repo_score), _) => {
+                    let (pos, neg) = repo_score.get_score_tuple();
+                    if pos + neg < min {
+                        let msg = format!("repo score {:.3} is below the minimum {:.3}", pos + neg, min);
+                        let row = worst_row(repo_score.get_score_table());
+                        violations.push(Violation::new(id, "min_repo_score", msg, row));
+                    }
+                },
+                // Reported as a failure by check()
+                (None, Some(_)) => (),
+                (None, None) => {
+                    let msg = "no repo score (unsupported or missing repository)".to_string();
+                    violations.push(Violation::new(id, "min_repo_score", msg, None));
+                },
+            }
+        }
+
+        violations
+    }
+
+    pub fn check(&self, results: &[(String, Result<Scores>)], crate_only: bool) -> PolicyReport {
+        let mut report = PolicyReport::default();
+
+        for res in results.iter().filter(|(id, _)| !self.ignore.contains(id)) {
+            report.checked += 1;
+            match *res {
+                (ref id, Ok(ref scores)) => {
+                    report.violations.extend(self.check_scores(id, scores, crate_only));
+
+                    // A repo score is needed, but we failed to get it
+                    if let (false, Some(_), Some(e)) = (crate_only, self.min_repo_score, scores.get_repo_error()) {
+                        report.failures.push((id.clone(), e.to_string()));
+                    }
+                },
+                (ref id, Err(ref e)) => {
+                    report.failures.push((id.clone(), e.to_string()));
+                },
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esr_crate::{CrateInfo, CrateInfoWithScore};
+    use crate::esr_errors::EsrError;
+
+    fn allowed(licenses: &[&str]) -> Vec<String> {
+        licenses.iter().map(|&l| l.into()).collect()
+    }
+
+    fn crate_scores(license: Option<&str>, yanked: bool, months: f64, unavailable: Vec<&'static str>) -> CrateInfoWithScore {
+        let crate_info: CrateInfo = serde_json::from_value(serde_json::json!({
+            "crate": {
+                "id": "foo",
+                "created_at": "2020-01-01T00:00:00.000000+00:00",
+                "updated_at": "2021-01-01T00:00:00.000000+00:00",
+                "max_version": "1.0.0",
+                "description": null,
+                "repository": null,
+                "documentation": null,
+                "homepage": null,
+                "recent_downloads": null,
+                "keywords": null,
+                "categories": null,
+            },
+            "versions": [{
+                "created_at": "2021-01-01T00:00:00.000000+00:00",
+                "downloads": 10,
+                "num": "1.0.0",
+                "yanked": yanked,
+                "license": license,
+            }],
+        })).unwrap();
+        CrateInfoWithScore::from_info_without_stats(crate_info, months, unavailable)
+    }
+
+    fn rules(violations: &[Violation]) -> Vec<&str> {
+        violations.iter().map(|v| v.get_rule()).collect()
+    }
+
+    #[test]
+    fn license_expressions() {
+        let mit_apache = allowed(&["MIT", "Apache-2.0"]);
+
+        assert!(Policy::license_allowed(&mit_apache, "MIT"));
+        assert!(Policy::license_allowed(&mit_apache, "mit"));
+        assert!(Policy::license_allowed(&mit_apache, "MIT OR GPL-3.0"));
+        assert!(Policy::license_allowed(&mit_apache, "GPL-3.0/MIT"));
+        assert!(Policy::license_allowed(&mit_apache, "MIT AND Apache-2.0"));
+        assert!(!Policy::license_allowed(&mit_apache, "MIT AND GPL-3.0"));
+        assert!(!Policy::license_allowed(&mit_apache, "GPL-3.0"));
+
+        // AND binds tighter than OR
+        assert!(Policy::license_allowed(&mit_apache, "GPL-3.0 AND Zlib OR MIT"));
+        assert!(Policy::license_allowed(&mit_apache, "MIT OR GPL-3.0 AND Zlib"));
+        assert!(!Policy::license_allowed(&mit_apache, "(MIT OR Apache-2.0) AND Unicode-DFS-2016"));
+        assert!(!Policy::license_allowed(&mit_apache, "MIT AND (GPL-3.0 OR Zlib)"));
+        assert!(Policy::license_allowed(&mit_apache, "((GPL-3.0 OR (MIT)) AND (Zlib OR Apache-2.0))"));
+
+        let with_unicode = allowed(&["MIT", "Apache-2.0", "Unicode-DFS-2016"]);
+        assert!(Policy::license_allowed(&with_unicode, "(MIT OR Apache-2.0) AND Unicode-DFS-2016"));
+
+        // Allowing a license allows it with any exception
+        assert!(Policy::license_allowed(&mit_apache, "Apache-2.0 WITH LLVM-exception"));
+        assert!(!Policy::license_allowed(&allowed(&["GPL-2.0"]), "GPL-3.0 WITH Classpath-exception-2.0"));
+        assert!(Policy::license_allowed(&allowed(&["gpl-2.0  with classpath-exception-2.0"]),
+                                        "GPL-2.0 WITH Classpath-exception-2.0"));
+
+        // Malformed
+        for license in &["", "MIT OR", "(MIT", "MIT)", "MIT Apache-2.0", "Apache-2.0 WITH", "AND MIT"] {
+            assert!(!Policy::license_allowed(&mit_apache, license), "{}", license);
+        }
+    }
+
+    #[test]
+    fn check_scores_rules() {
+        let policy = Policy::from_toml_str(r#"
+            min_crate_score = -100.0
+            min_repo_score = 10.0
+            max_months_since_last_release = 12.0
+            forbid_empty_or_all_yanked = true
+            allowed_licenses = ["MIT"]
+        "#).unwrap();
+
+        let scores = Scores::CrateOnly(crate_scores(Some("GPL-3.0"), true, 30.0, Vec::new()));
+        assert_eq!(rules(&policy.check_scores("foo", &scores, true)),
+                   ["min_crate_score", "max_months_since_last_release", "forbid_empty_or_all_yanked", "allowed_licenses"]);
+        let violations = policy.check_scores("foo", &scores, false);
+        assert_eq!(rules(&violations[4..]), ["min_repo_score"]);
+        assert_eq!(violations[0].get_factor().map(|row| &*row.factor), Some("empty_or_all_yanked"));
+
+        // Rules based on unavailable info are skipped
+        let unavailable = vec!["has_license", "months_since_last_release"];
+        let scores = Scores::CrateOnly(crate_scores(None, false, 30.0, unavailable));
+        assert!(policy.check_scores("foo", &scores, true).is_empty());
+
+        let scores = Scores::CrateOnly(crate_scores(None, false, 6.0, Vec::new()));
+        assert_eq!(rules(&policy.check_scores("foo", &scores, true)), ["allowed_licenses"]);
+    }
+
+    #[test]
+    fn check_exit_codes() {
+        let policy = Policy::from_toml_str(r#"
+            min_repo_score = 10.0
+            allowed_licenses = ["MIT"]
+            ignore = ["ignored"]
+        "#).unwrap();
+        let ok = || Ok(Scores::CrateOnly(crate_scores(Some("MIT"), false, 1.0, Vec::new())));
+        let repo_failed = || Ok(Scores::CrateAndRepo(crate_scores(Some("MIT"), false, 1.0, Vec::new()),
+                                                     Err(EsrError::Other("no repo".into()))));
+        let failed = || Err(EsrError::Other("no crate".into()));
+        let bad_license = || Ok(Scores::CrateOnly(crate_scores(Some("GPL-3.0"), false, 1.0, Vec::new())));
+
+        let report = policy.check(&[("foo".into(), ok()), ("ignored".into(), failed())], true);
+        assert_eq!((report.get_checked(), report.passed(), report.exit_code()), (1, true, 0));
+
+        let report = policy.check(&[("foo".into(), ok()), ("bar".into(), bad_license())], true);
+        assert_eq!((report.passed(), report.exit_code()), (false, EXIT_VIOLATIONS));
+
+        // A failed repo score is a failure, not a violation. But only if needed.
+        let report = policy.check(&[("foo".into(), repo_failed())], true);
+        assert_eq!(report.exit_code(), 0);
+        let report = policy.check(&[("foo".into(), repo_failed())], false);
+        assert_eq!((report.get_failures().len(), report.get_violations().len()), (1, 0));
+        assert_eq!(report.exit_code(), EXIT_FETCH_FAILED);
+
+        // Failures take precedence over violations
+        let report = policy.check(&[("foo".into(), bad_license()), ("bar".into(), failed())], true);
+        assert_eq!((report.get_failures().len(), report.get_violations().len()), (1, 1));
+        assert_eq!(report.exit_code(), EXIT_FETCH_FAILED);
+    }
+}
@@ -10,9 +10,18 @@
 */
 
 use crate::esr_errors::{Result, EsrError};
+use crate::esr_score::{ScoreRow, CompareRow};
+use crate::esr_policy::PolicyReport;
+use crate::esr_cache::CacheStats;
+use crate::esr_ratelimit::RateLimit;
+use crate::esr_crate::{Category, Owner};
+use crate::esr_score::{self, OwnerPortfolio};
+use crate::esr_history::{self, Snapshot, Trend};
 use term_string::{TermString, TermStyle};
 use term_string::color as C;
 
+use std::path::Path;
+
 const BOLD : fn() -> TermStyle = || TermStyle::bold();
 const RED_BOLD : fn() -> TermStyle = || TermStyle::fg(C::RED).with_bold();
 const GREEN_BOLD : fn() -> TermStyle = || TermStyle::fg(C::GREEN).with_bold();
@@ -40,6 +49,10 @@ impl EsrPrinter {
         TermString::new(RED_BOLD(), "(empty/all yanked)")
     }
 
+    pub fn from_index() -> TermString {
+        TermString::new(YELLOW_BOLD(), "(offline, from local index)")
+    }
+
     pub fn desc(orig_desc: &str) -> String {
         let desc = String::from(orig_desc);
 
@@ -84,6 +97,10 @@ impl EsrPrinter {
         }
     }
 
+    pub fn release_undated(ver_opt: Option<&str>) -> String {
+        ver_opt.unwrap_or("N/A").into()
+    }
+
     pub fn releases(stable: usize, non_yanked_pre: usize, yanked: usize) -> TermString {
         let pos_sign = || TermString::new(BOLD(), "+");
         let stable_f = TermString::new(GREEN_BOLD(), format!("{}", stable));
@@ -92,6 +109,21 @@ impl EsrPrinter {
         stable_f + pos_sign() + non_yanked_pre_f + pos_sign() + yanked_f
     }
 
+    // e.g. "12345 in the last 90 days (+12.5% in the last 30), weekly: ▃▄▅▅▆"
+    pub fn downloads(total: usize, growth_pct: f64, daily: &[usize]) -> String {
+        if daily.is_empty() {
+            return "N/A".into();
+        }
+
+        let weekly: Vec<_> = daily.rchunks(7)
+            .rev()
+            .map(|week| week.iter().sum::<usize>() as f64)
+            .collect();
+
+        format!("{} in the last 90 days ({:+.1}% in the last 30), weekly: {}",
+                total, growth_pct, Self::sparkline(&weekly))
+    }
+
     pub fn score_error(msg: &str) -> TermString {
         TermString::new(RED_BOLD(), msg) + ": " + TermString::new(TermStyle::bold(), "Error") + "\n "
     }
@@ -110,7 +142,31 @@ impl EsrPrinter {
         Self::msg_pair(msg, tail)
     }
 
-    pub fn score_details(msg: &str, table: &[(String, String, String)]) -> TermString {
+    pub fn score_value(val: f64) -> String {
+        // Counts are shown as integers
+        match val.fract() == 0.0 && val.abs() < 1e15 {
+            true => format!("{}", val as i64),
+            false => format!("{:.3}", val),
+        }
+    }
+
+    // Counts without decimals, unless shaped
+    fn row_value(row: &ScoreRow, value: f64) -> String {
+        match row.count && row.exponent == 1.0 {
+            true => format!("{}", value as i64),
+            false => format!("{:.3}", value),
+        }
+    }
+
+    // Shaped factor name and value
+    fn score_row_factor(row: &ScoreRow) -> (String, f64) {
+        match row.exponent == 1.0 {
+            true => (row.factor.clone(), row.value),
+            false => (format!("{}.powf({})", row.factor, row.exponent), row.value.powf(row.exponent)),
+        }
+    }
+
+    pub fn score_details(msg: &str, table: &[ScoreRow]) -> TermString {
         let msg = format!("|{: ^83}|", msg);
         let frame ="-".repeat(85);
 
@@ -122,16 +178,35 @@ impl EsrPrinter {
         score_formatted += TermString::new(CYAN_BOLD(), &*msg) + "\n";
         score_formatted += frame_line();
 
-        for line in table {
-            if line.1.find("* -").is_some() {
-                score_formatted += sep() + TermString::new(YELLOW_BOLD(), &*line.0) + sep();
-                score_formatted += TermString::new(RED_BOLD(), &*line.1) + sep();
-                score_formatted += TermString::new(RED_BOLD(), format!("{: ^11}", line.2)) + sep() + "\n";
+        for row in table {
+            // Factors without a weight in the profile don't change scores
+            if row.weight == 0.0 {
+                continue;
+            }
+
+            if !row.available {
+                let factor = format!("{: ^49}", row.factor.clone() + " (unavailable)");
+                score_formatted += sep() + TermString::new(YELLOW_BOLD(), &*factor) + sep();
+                score_formatted += TermString::new(BOLD(), format!("{: ^18}", "N/A")) + sep();
+                score_formatted += TermString::new(BOLD(), format!("{: ^11}", "N/A")) + sep() + "\n";
+                score_formatted += frame_line();
+                continue;
+            }
+
+            let (factor, value) = Self::score_row_factor(row);
+            let factor = format!("{: ^49}", factor);
+            let value_mul_weight = format!("{: ^18}", format!("{} * {:.3}", Self::row_value(row, value), row.weight));
+            let contribution = format!("{:0.3}", row.contribution);
+
+            if row.weight < 0.0 {
+                score_formatted += sep() + TermString::new(YELLOW_BOLD(), &*factor) + sep();
+                score_formatted += TermString::new(RED_BOLD(), &*value_mul_weight) + sep();
+                score_formatted += TermString::new(RED_BOLD(), format!("{: ^11}", contribution)) + sep() + "\n";
                 score_formatted += frame_line();
             } else {
-                score_formatted += sep() + TermString::new(YELLOW_BOLD(), &*line.0) + sep();
-                score_formatted += TermString::new(GREEN_BOLD(), &*line.1) + sep();
-                score_formatted += TermString::new(GREEN_BOLD(), format!("{: ^11}", "+".to_string() + &*line.2)) + sep() + "\n";
+                score_formatted += sep() + TermString::new(YELLOW_BOLD(), &*factor) + sep();
+                score_formatted += TermString::new(GREEN_BOLD(), &*value_mul_weight) + sep();
+                score_formatted += TermString::new(GREEN_BOLD(), format!("{: ^11}", "+".to_string() + &*contribution)) + sep() + "\n";
                 score_formatted += frame_line();
             }
         }
@@ -139,13 +214,161 @@ impl EsrPrinter {
         score_formatted
     }
 
+    pub fn compare_table(ids: &[&str], sections: &[(String, Vec<CompareRow>)]) -> TermString {
+        let all_rows = || sections.iter().flat_map(|(_, rows)| rows.iter());
+
+        // Columns fit their widest cell
+        let label_width = all_rows().map(|row| row.label.len()).max().unwrap_or(0) + 1;
+        let widths: Vec<_> = ids.iter()
+            .enumerate()
+            .map(|(i, id)| {
+                all_rows()
+                    .filter_map(|row| row.cells.get(i).and_then(|cell| cell.as_ref()).map(|cell| cell.len()))
+                    .chain(Some(id.len()))
+                    .max()
+                    .unwrap_or(0) + 1
+            })
+            .collect();
+        let frame_width = widths.iter().chain(Some(&label_width)).map(|w| w + 2).sum::<usize>() + 1;
+
+        let frame = "-".repeat(frame_width);
+        let sep = || TermString::new(CYAN_BOLD(), "| ");
+        let frame_line = || TermString::new(CYAN_BOLD(), &*frame) + "\n";
+
+        let mut ret = TermString::default();
+        ret += frame_line();
+        ret += sep() + format!("{: ^1$}", "", label_width);
+        for (id, width) in ids.iter().zip(&widths) {
+            ret += sep() + TermString::new(BLUE_BOLD(), format!("{: ^1$}", id, width));
+        }
+        ret += TermString::new(CYAN_BOLD(), "|") + "\n";
+
+        for (title, rows) in sections {
+            ret += frame_line();
+            ret += TermString::new(CYAN_BOLD(), format!("|{: ^1$}|", title, frame_width - 2)) + "\n";
+            ret += frame_line();
+
+            for row in rows {
+                ret += sep() + TermString::new(YELLOW_BOLD(), format!("{: ^1$}", row.label, label_width));
+                for (i, width) in widths.iter().enumerate() {
+                    let cell = row.cells.get(i).and_then(|cell| cell.as_ref().map(|c| c.as_str())).unwrap_or("N/A");
+                    let style = match row.winners.contains(&i) {
+                        true => GREEN_BOLD(),
+                        false => BOLD(),
+                    };
+                    ret += sep() + TermString::new(style, format!("{: ^1$}", cell, width));
+                }
+                ret += TermString::new(CYAN_BOLD(), "|") + "\n";
+            }
+        }
+        ret += frame_line();
+
+        ret
+    }
+
+    // e.g. "▁▃▅█", scaled between the min and max values
+    pub fn sparkline(values: &[f64]) -> String {
+        const BARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        values.iter()
+            .map(|&val| match max - min > 0.0 {
+                true => BARS[(((val - min) / (max - min)) * (BARS.len() - 1) as f64).round() as usize],
+                false => BARS[BARS.len() / 2],
+            })
+            .collect()
+    }
+
+    pub fn history(name: &str, snapshots: &[Snapshot]) -> TermString {
+        // Only the most recent snapshots fit in a sparkline
+        const MAX_POINTS: usize = 30;
+
+        let mut ret = TermString::default();
+        for &(kind, title) in &[("crate", "Crate Score History"), ("repo", "Repo Score History")] {
+            let of_kind: Vec<_> = snapshots.iter().filter(|s| s.get_kind() == kind).collect();
+            let (first, last) = match (of_kind.first(), of_kind.last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => continue,
+            };
+
+            let title = format!("{} of {} ({} snapshots, {} to {})",
+                                title, name, of_kind.len(), first.get_recorded_at(), last.get_recorded_at());
+            ret += Self::history_table(&title, &esr_history::trends(snapshots, kind), MAX_POINTS) + "\n";
+        }
+        ret
+    }
+
+    fn history_table(msg: &str, trends: &[Trend], max_points: usize) -> TermString {
+        let msg = format!("|{: ^1$}|", msg, 80 + max_points);
+        let frame ="-".repeat(82 + max_points);
+
+        let sep = || TermString::new(CYAN_BOLD(), "| ");
+        let frame_line = || TermString::new(CYAN_BOLD(), &*frame) + "\n";
+
+        let mut ret = TermString::default();
+        ret += frame_line();
+        ret += TermString::new(CYAN_BOLD(), &*msg) + "\n";
+        ret += frame_line();
+
+        for trend in trends {
+            let points = &trend.values[trend.values.len().saturating_sub(max_points)..];
+            let (first, last) = (trend.values[0], trend.values[trend.values.len() - 1]);
+            let change = trend.change();
+
+            let change_style = match (change == 0.0, (change > 0.0) == trend.higher_is_better) {
+                (true, _) => BOLD(),
+                (false, true) => GREEN_BOLD(),
+                (false, false) => RED_BOLD(),
+            };
+
+            ret += sep() + TermString::new(YELLOW_BOLD(), format!("{: ^40}", trend.label));
+            ret += sep() + TermString::new(BOLD(), format!("{: ^10}", Self::score_value(first)));
+            ret += sep() + TermString::new(BOLD(), format!("{: ^10}", Self::score_value(last)));
+            ret += sep() + TermString::new(change_style, format!("{: ^10}", format!("{:+.3}", change)));
+            ret += sep() + TermString::new(CYAN_BOLD(), format!("{: <1$}", Self::sparkline(points), max_points + 1)) + sep() + "\n";
+        }
+        ret += frame_line();
+
+        ret
+    }
+
+    pub fn history_empty(name: &str) -> TermString {
+        let msg = format!("No recorded scores for \"{}\". Record some with --record.", name);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn history_failed(e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to read score history.", e);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn record_failed(id: &str, e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to record scores of \"{}\".", e, id);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    // What to do about `e`, if anything
+    fn error_hint(e: &EsrError) -> &'static str {
+        match *e {
+            EsrError::NotFound(..) => " Maybe it does not exist.",
+            EsrError::Unauthorized(..) => " Maybe the access token is invalid or lacks permissions.",
+            EsrError::RateLimited(..) => " Try again later.",
+            EsrError::ClientError(429, ..) => " Try again later.",
+            EsrError::ServerError(..) => " The server may be down, try again later.",
+            EsrError::Offline(_) => " Try again without --offline.",
+            _ => "",
+        }
+    }
+
     pub fn crate_no_score(id: &str, e: &EsrError) -> TermString {
-        let msg = format!("{}.\nFailed to get scores for crate \"{}\". Maybe it does not exist.", e, id);
+        let msg = format!("{}.\nFailed to get scores for crate \"{}\".{}", e, id, Self::error_hint(e));
         TermString::new(RED_BOLD(), msg)
     }
 
     pub fn repo_no_score(repo: &str, e: &EsrError) -> TermString {
-        let msg = format!("{}.\nFailed to get scores for repo \"{}\". Maybe it does not exist.", e, repo);
+        let msg = format!("{}.\nFailed to get scores for repo \"{}\".{}", e, repo, Self::error_hint(e));
         TermString::new(RED_BOLD(), msg)
     }
 
@@ -180,6 +403,148 @@ impl EsrPrinter {
         TermString::new(YELLOW_BOLD(), msg)
     }
 
+    pub fn deps_failed(manifest_path: &Path, e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to get dependencies from \"{}\".", e, manifest_path.display());
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn policy_failed(policy_path: &Path, e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to load policy from \"{}\".", e, policy_path.display());
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn policy_report(report: &PolicyReport) -> TermString {
+        let mut ret = TermString::default();
+
+        for v in report.get_violations() {
+            ret += Self::id(v.get_crate_name()) + ": ";
+            ret += TermString::new(YELLOW_BOLD(), v.get_rule()) + ": " + v.get_message() + "\n";
+
+            if let Some(row) = v.get_factor() {
+                let (factor, value) = Self::score_row_factor(row);
+                let detail = format!("    {} = {} * {:.3} = {:.3}\n", factor, Self::score_value(value), row.weight, row.contribution);
+                ret += TermString::new(RED_BOLD(), detail);
+            }
+        }
+
+        for (id, e) in report.get_failures() {
+            ret += Self::err(&format!("{}: Failed to get score info: {}.", id, e)) + "\n";
+        }
+
+        let summary = format!("Checked {} crate(s): {} violation(s), {} failure(s).",
+                              report.get_checked(),
+                              report.get_violations().len(),
+                              report.get_failures().len());
+
+        match report.passed() {
+            true => ret += TermString::new(GREEN_BOLD(), summary),
+            false => ret += TermString::new(RED_BOLD(), summary),
+        }
+
+        ret
+    }
+
+    pub fn cache_stats(dir: Option<&Path>, stats: &CacheStats) -> TermString {
+        let dir = dir.map(|d| d.display().to_string()).unwrap_or_else(|| "N/A".into());
+        let size = format!("{:.2} MiB", stats.get_size_bytes() as f64 / (1024.0 * 1024.0));
+
+        let mut ret = TermString::default() + " ";
+        ret += Self::msg_pair("Directory", dir);
+        ret += Self::msg_pair("Entries  ", format!("{} ({} expired)", stats.get_entries(), stats.get_expired()));
+        ret += Self::msg_pair("Size     ", size);
+        for (host, entries) in stats.get_hosts() {
+            ret += Self::msg_pair("Host     ", format!("{} ({} entries)", host, entries));
+        }
+        ret
+    }
+
+    pub fn cache_cleared(removed: usize) -> TermString {
+        TermString::new(GREEN_BOLD(), format!("Removed {} cached entries.", removed))
+    }
+
+    pub fn cache_failed(e: &EsrError) -> TermString {
+        let msg = format!("{}.\nCache operation failed.", e);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn categories(categories: &[Category]) -> TermString {
+        let slug_width = categories.iter().map(|c| c.get_slug().len()).max().unwrap_or(0);
+
+        let mut ret = TermString::default();
+        for (num, category) in categories.iter().enumerate() {
+            if num > 0 {
+                ret += "\n";
+            }
+            ret += Self::id(&format!(" {: <1$}", category.get_slug(), slug_width));
+            ret += TermString::new(BOLD(), format!(" {: >7} crates", category.get_crates_cnt()));
+            ret += format!("  {}", category.get_category());
+        }
+        ret
+    }
+
+    pub fn categories_failed(e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to get categories.", e);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn owner_portfolio(owner: &Owner, portfolio: &OwnerPortfolio) -> TermString {
+        let owner_msg = match owner.get_name() {
+            Some(name) => format!("{} ({})", owner.get_login(), name),
+            None => owner.get_login().into(),
+        };
+        let stale_msg = format!("{} ({:.1}%) with no release in {} months, or all yanked",
+                                portfolio.get_stale(), portfolio.get_stale_pct(), esr_score::STALE_MONTHS);
+
+        let mut ret = TermString::default() + " ";
+        ret += Self::msg_pair("Owner      ", owner_msg);
+        ret += Self::msg_pair("Crates     ", format!("{} ({} scored)", portfolio.get_crates(), portfolio.get_scored()));
+        ret += Self::msg_pair("Dependants ", format!("{} from non owners", portfolio.get_dependants_from_non_owners()));
+        ret += Self::msg_pair("Stale      ", stale_msg);
+        ret
+    }
+
+    pub fn owner_no_crates(login: &str) -> TermString {
+        let msg = format!("\"{}\" owns no crates.", login);
+        TermString::new(YELLOW_BOLD(), msg)
+    }
+
+    pub fn owner_failed(login: &str, e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to get crates of \"{}\". Maybe it's not a crates.io user or team.", e, login);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn rate_limits(rate_limits: &[RateLimit]) -> TermString {
+        let mut ret = TermString::default() + " ";
+        for rate_limit in rate_limits {
+            let limit = rate_limit.get_limit().map(|l| format!("/{}", l)).unwrap_or_default();
+            let resets_in = rate_limit.resets_in()
+                .map(|secs| format!(", resets in {} minutes", (secs + 59) / 60))
+                .unwrap_or_default();
+            let msg = format!("{}{} requests left on {}{}", rate_limit.get_remaining(), limit, rate_limit.get_host(), resets_in);
+
+            ret += match rate_limit.get_remaining() {
+                0 => Self::msg_pair("Rate Limit ", TermString::new(RED_BOLD(), msg)),
+                _ => Self::msg_pair("Rate Limit ", msg),
+            };
+        }
+        ret
+    }
+
+    pub fn registry_failed(name: &str, e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to use registry \"{}\".", e, name);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn config_failed(e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to load config.", e);
+        TermString::new(RED_BOLD(), msg)
+    }
+
+    pub fn weights_failed(e: &EsrError) -> TermString {
+        let msg = format!("{}.\nFailed to load weights profile.", e);
+        TermString::new(RED_BOLD(), msg)
+    }
+
     pub fn crate_index_init() -> TermString {
         TermString::new(CYAN_BOLD(), "Crates index is initializing/updating, this may take a few seconds...")
     }
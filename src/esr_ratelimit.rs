@@ -0,0 +1,202 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use isahc::http::{HeaderMap, StatusCode};
+use serde::Serialize;
+use once_cell::sync::OnceCell;
+
+use crate::esr_errors::{Result, EsrError};
+
+// Longest we pause for a rate limit to reset. Requests fail with
+// `EsrError::RateLimited` instead if it's further away.
+pub const MAX_WAIT_SECS: u64 = 60;
+
+// Wait this long if a rate limited response doesn't say for how long
+const DEFAULT_WAIT_SECS: u64 = 60;
+
+// Budgets lower than this are logged as low
+const LOW_BUDGET: u64 = 100;
+
+// The last seen rate limit of a host, from `X-RateLimit-*` (GitHub, Gitea)
+// or `RateLimit-*` (GitLab) response headers
+#[derive(Serialize, Debug, Clone)]
+pub struct RateLimit {
+    host: String,
+    limit: Option<u64>,
+    remaining: u64,
+    // Unix timestamp
+    reset: Option<u64>,
+}
+
+impl RateLimit {
+    fn from_headers(host: &str, headers: &HeaderMap) -> Option<Self> {
+        Some(Self {
+            host: host.into(),
+            limit: header_u64(headers, "limit"),
+            remaining: header_u64(headers, "remaining")?,
+            reset: header_u64(headers, "reset"),
+        })
+    }
+
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn get_limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn get_remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    // Seconds until the budget is reset, if known
+    pub fn resets_in(&self) -> Option<u64> {
+        self.reset.map(|reset| reset.saturating_sub(now()))
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.remaining == 0 && self.resets_in().map(|secs| secs > 0).unwrap_or(false)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// e.g. "api.github.com"
+pub(crate) fn host_of(url: &str) -> &str {
+    url.split('/').nth(2).unwrap_or(url)
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(format!("x-ratelimit-{}", name).as_str())
+        .or_else(|| headers.get(format!("ratelimit-{}", name).as_str()))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+fn get_limits() -> &'static Mutex<BTreeMap<String, RateLimit>> {
+    static LIMITS: OnceCell<Mutex<BTreeMap<String, RateLimit>>> = OnceCell::new();
+    LIMITS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+// Last seen rate limits of all hosts
+pub fn rate_limits() -> Vec<RateLimit> {
+    get_limits().lock()
+        .map(|limits| limits.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+// Record the rate limit in the response headers of `url`
+pub(crate) fn update(url: &str, headers: &HeaderMap) {
+    let host = host_of(url);
+    let rate_limit = match RateLimit::from_headers(host, headers) {
+        Some(rate_limit) => rate_limit,
+        None => return,
+    };
+
+    let low = match rate_limit.remaining < LOW_BUDGET {
+        true => " (low)",
+        false => "",
+    };
+    log::debug!("Rate limit budget of '{}'{}: {}/{} left, resets in {}s",
+                host, low, rate_limit.remaining,
+                rate_limit.limit.map(|l| l.to_string()).unwrap_or_else(|| "?".into()),
+                rate_limit.resets_in().map(|s| s.to_string()).unwrap_or_else(|| "?".into()));
+
+    if let Ok(mut limits) = get_limits().lock() {
+        limits.insert(host.into(), rate_limit);
+    }
+}
+
+// How long to pause before sending a request to `url`. Fails without
+// pausing if the budget of its host won't be reset soon.
+pub(crate) fn wait_before(url: &str) -> Result<Option<Duration>> {
+    let host = host_of(url);
+    let resets_in = get_limits().lock().ok()
+        .and_then(|limits| limits.get(host).filter(|l| l.is_exhausted()).and_then(|l| l.resets_in()));
+
+    match resets_in {
+        Some(secs) if secs > MAX_WAIT_SECS => Err(EsrError::RateLimited(host.into(), secs)),
+        Some(secs) => Ok(Some(Duration::from_secs(secs))),
+        None => Ok(None),
+    }
+}
+
+// Seconds until a request to `url` can be retried, if the response was
+// rate limited
+pub(crate) fn limited_for(url: &str, status: StatusCode, headers: &HeaderMap) -> Option<u64> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let retry_after = headers.get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+
+    let rate_limit = RateLimit::from_headers(host_of(url), headers);
+    let exhausted = rate_limit.as_ref().map(|l| l.remaining == 0).unwrap_or(false);
+
+    match (retry_after, exhausted) {
+        (Some(secs), _) => Some(secs),
+        (None, true) => rate_limit.and_then(|l| l.resets_in()).or(Some(DEFAULT_WAIT_SECS)),
+        // Not all 403s are about rate limits. Neither are 429s that don't
+        // say anything about it, which are left to the retry policy.
+        (None, false) if status == StatusCode::FORBIDDEN || !has_rate_limit_headers(headers) => None,
+        (None, false) => Some(DEFAULT_WAIT_SECS),
+    }
+}
+
+fn has_rate_limit_headers(headers: &HeaderMap) -> bool {
+    headers.keys().any(|name| name.as_str().starts_with("x-ratelimit-") || name.as_str().starts_with("ratelimit-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use isahc::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn limited_for_statuses_and_headers() {
+        let url = "https://api.github.com/repos/foo/bar";
+        let reset = (now() + 30).to_string();
+
+        assert_eq!(limited_for(url, StatusCode::TOO_MANY_REQUESTS, &headers(&[])), None);
+        assert_eq!(limited_for(url, StatusCode::TOO_MANY_REQUESTS, &headers(&[("retry-after", "7")])), Some(7));
+        assert_eq!(limited_for(url, StatusCode::TOO_MANY_REQUESTS, &headers(&[("x-ratelimit-limit", "60")])),
+                   Some(DEFAULT_WAIT_SECS));
+        let secs = limited_for(url, StatusCode::TOO_MANY_REQUESTS,
+                               &headers(&[("ratelimit-remaining", "0"), ("ratelimit-reset", &reset)]));
+        assert!(secs.map(|secs| secs > 0 && secs <= 30).unwrap_or(false), "{:?}", secs);
+
+        assert_eq!(limited_for(url, StatusCode::FORBIDDEN, &headers(&[])), None);
+        assert_eq!(limited_for(url, StatusCode::FORBIDDEN, &headers(&[("x-ratelimit-remaining", "10")])), None);
+        assert_eq!(limited_for(url, StatusCode::FORBIDDEN, &headers(&[("retry-after", "7")])), Some(7));
+        assert_eq!(limited_for(url, StatusCode::OK, &headers(&[("retry-after", "7")])), None);
+    }
+}
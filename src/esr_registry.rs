@@ -0,0 +1,210 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use serde::Deserialize;
+use once_cell::sync::OnceCell;
+
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_index;
+
+pub const CRATES_IO_API: &str = "https://crates.io";
+
+#[derive(Debug, Clone)]
+pub struct Registry {
+    // None for crates.io
+    name: Option<String>,
+    // As in cargo config (e.g. "sparse+https://index.example.com/"). None for the default index.
+    index: Option<String>,
+    // e.g. "https://crates.io", without a trailing slash. Set from the index's
+    // `config.json` once the registry is active, as fetching it may need the token.
+    api: OnceCell<String>,
+    token: Option<String>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            name: None,
+            index: None,
+            api: OnceCell::from(String::from(CRATES_IO_API)),
+            token: None,
+        }
+    }
+}
+
+static REGISTRY: OnceCell<Registry> = OnceCell::new();
+
+// Get the active registry. crates.io is used if `set_registry()` was never called.
+pub fn get_registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+pub fn set_registry(registry: Registry) -> Result<()> {
+    REGISTRY.set(registry)
+        .map_err(|_| EsrError::from("Registry already set"))
+}
+
+// Point both the index and the API at registry `name` from cargo config
+pub async fn use_registry(name: &str) -> Result<()> {
+    set_registry(Registry::from_cargo_config(name)?)?;
+    let registry = get_registry();
+
+    match registry.get_index() {
+        Some(index) if registry.is_sparse() => esr_index::set_sparse_index_url(index)?,
+        Some(index) => esr_index::set_git_index_url(index)?,
+        None => return Ok(()),
+    }
+
+    let api = esr_index::index_api().await?
+        .ok_or_else(|| EsrError::Other(format!("Registry \"{}\" has no API", name)))?;
+    registry.api.set(api.trim_end_matches('/').into())
+        .map_err(|_| EsrError::from("Registry API already set"))
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct CargoRegistry {
+    index: Option<String>,
+    token: Option<String>,
+}
+
+// The parts of `.cargo/config.toml` (and `credentials.toml`) we use
+#[derive(Deserialize, Debug, Default)]
+struct CargoConfig {
+    #[serde(default)]
+    registries: HashMap<String, CargoRegistry>,
+}
+
+impl CargoConfig {
+    fn from_path(path: &Path) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|dir| dir.join(".cargo")))
+}
+
+// Config files in the order cargo gives them precedence. The current dir and
+// its parents first, then `$CARGO_HOME`.
+fn cargo_config_paths() -> Vec<PathBuf> {
+    let project_dirs = env::current_dir()
+        .map(|dir| dir.ancestors().map(|d| d.join(".cargo")).collect())
+        .unwrap_or_else(|_| Vec::new());
+
+    project_dirs.into_iter()
+        .chain(cargo_home())
+        .flat_map(|dir| vec![dir.join("config.toml"), dir.join("config")])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn cargo_credentials_paths() -> Vec<PathBuf> {
+    cargo_home()
+        .map(|dir| vec![dir.join("credentials.toml"), dir.join("credentials")])
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+// First value of `field` set for registry `name` in `paths`
+fn registry_field<F>(paths: &[PathBuf], name: &str, field: F) -> Result<Option<String>>
+    where F: Fn(&CargoRegistry) -> Option<&String>
+{
+    for path in paths {
+        let config = CargoConfig::from_path(path)
+            .map_err(|e| EsrError::Other(format!("{}: {}", path.display(), e)))?;
+
+        if let Some(value) = config.registries.get(name).and_then(&field) {
+            return Ok(Some(value.clone()));
+        }
+    }
+    Ok(None)
+}
+
+impl Registry {
+    // Read registry `name` from cargo config, like cargo does. Environment
+    // variables (e.g. `CARGO_REGISTRIES_NAME_INDEX`) take precedence.
+    //
+    // The API base URL is not in cargo config. It's set by `use_registry()`
+    // from the index's `config.json`.
+    pub fn from_cargo_config(name: &str) -> Result<Self> {
+        if name == "crates-io" {
+            return Ok(Self::default());
+        }
+
+        let env_prefix = format!("CARGO_REGISTRIES_{}_", name.to_uppercase().replace('-', "_"));
+
+        let index = match env::var(env_prefix.clone() + "INDEX") {
+            Ok(index) => index,
+            Err(_) => registry_field(&cargo_config_paths(), name, |r| r.index.as_ref())?
+                .ok_or_else(|| EsrError::Other(format!("Registry \"{}\" not found in cargo config", name)))?,
+        };
+
+        let token = match env::var(env_prefix + "TOKEN") {
+            Ok(token) => Some(token),
+            Err(_) => match registry_field(&cargo_credentials_paths(), name, |r| r.token.as_ref())? {
+                Some(token) => Some(token),
+                None => registry_field(&cargo_config_paths(), name, |r| r.token.as_ref())?,
+            },
+        };
+
+        Ok(Self {
+            name: Some(name.into()),
+            index: Some(index),
+            api: OnceCell::new(),
+            token,
+        })
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn get_index(&self) -> Option<&str> {
+        self.index.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.get_index().map(|index| index.starts_with("sparse+")).unwrap_or(false)
+    }
+
+    pub fn get_api(&self) -> &str {
+        self.api.get().map(|s| s.as_str()).unwrap_or(CRATES_IO_API)
+    }
+
+    // e.g. "https://crates.io/api/v1/crates/serde"
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v1/{}", self.get_api(), path)
+    }
+
+    // Only requests to this registry's API and (sparse) index get the token
+    pub(crate) fn token_for_url(&self, url: &str) -> Option<&str> {
+        let token = self.token.as_ref()?;
+        let api_match = self.api.get()
+            .map(|api| url.starts_with(&*(api.clone() + "/")))
+            .unwrap_or(false);
+        let index_match = self.is_sparse() && self.get_index()
+            .map(|index| url.starts_with(index.trim_start_matches("sparse+")))
+            .unwrap_or(false);
+
+        match api_match || index_match {
+            true => Some(token),
+            false => None,
+        }
+    }
+}
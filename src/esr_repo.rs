@@ -0,0 +1,428 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use once_cell::sync::OnceCell;
+use async_trait::async_trait;
+
+use crate::esr_util;
+use crate::esr_errors::{Result, EsrError};
+use crate::esr_score::ScoreRow;
+use crate::esr_weights::{self, Weights};
+use crate::esr_github::GitHubProvider;
+use crate::esr_gitlab::GitLabProvider;
+use crate::esr_gitea::GiteaProvider;
+use crate::esr_git::{GitProvider, GitStats};
+
+// Normalized repo info, filled by repo providers.
+// All dates are ISO 8601 UTC (e.g. "2017-08-01T12:00:00Z"). See `esr_util::to_iso8601()`.
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PullRequestInfo {
+    pub merged_at: Option<String>,
+    pub number: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct IssueInfo {
+    pub closed_at: Option<String>,
+    pub number: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ContributorInfo {
+    pub contributions: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RepoGeneralInfo {
+    pub subscribers_count: usize,
+    pub created_at: String,
+    // Same as created_at if the repo is empty
+    pub pushed_at: String,
+}
+
+// =================
+
+#[derive(Serialize, Debug)]
+pub struct RepoInfo {
+    general_info: RepoGeneralInfo,
+    // Most recent first
+    last_100_closed_issues: Vec<IssueInfo>,
+    // Most recent first. More than 100 if more pages are allowed in config.
+    last_100_pull_requests: Vec<PullRequestInfo>,
+    // Most contributions first. More than 100 if more pages are allowed in config.
+    top_100_contributors: Vec<ContributorInfo>,
+    // Only from `GitProvider`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_stats: Option<GitStats>,
+}
+
+impl RepoInfo {
+    pub fn new(general_info: RepoGeneralInfo,
+               last_100_closed_issues: Vec<IssueInfo>,
+               last_100_pull_requests: Vec<PullRequestInfo>,
+               top_100_contributors: Vec<ContributorInfo>) -> Self {
+        Self {
+            general_info,
+            last_100_closed_issues,
+            last_100_pull_requests,
+            top_100_contributors,
+            git_stats: None,
+        }
+    }
+
+    pub fn with_git_stats(mut self, git_stats: GitStats) -> Self {
+        self.git_stats = Some(git_stats);
+        self
+    }
+
+    pub fn get_git_stats(&self) -> Option<&GitStats> {
+        self.git_stats.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoSource {
+    // Forge APIs (GitHub, GitLab, ...)
+    Api,
+    // Cloned git history, whatever the forge
+    Git,
+}
+
+static REPO_SOURCE: OnceCell<RepoSource> = OnceCell::new();
+
+pub fn get_repo_source() -> RepoSource {
+    *REPO_SOURCE.get_or_init(|| RepoSource::Api)
+}
+
+pub fn set_repo_source(source: RepoSource) -> Result<()> {
+    REPO_SOURCE.set(source)
+        .map_err(|_| EsrError::from("Repo source already set"))
+}
+
+// A source of repo info (e.g. GitHub). Library users can add their own
+// with `register_provider()`.
+#[async_trait]
+pub trait RepoProvider: Send + Sync {
+    // Shown with repo scores (e.g. "GitHub")
+    fn name(&self) -> &str;
+
+    // Get a provider-specific repo id, if `repo` (a URL or a path) is recognized
+    fn detect(&self, repo: &str) -> Option<String>;
+
+    async fn fetch(&self, id: String) -> Result<RepoInfo>;
+}
+
+static PROVIDERS: OnceCell<RwLock<Vec<Arc<dyn RepoProvider>>>> = OnceCell::new();
+
+fn registered_providers() -> &'static RwLock<Vec<Arc<dyn RepoProvider>>> {
+    PROVIDERS.get_or_init(|| RwLock::new(Vec::new()))
+}
+
+// Registered providers are tried in order, before the built-in ones
+pub fn register_provider(provider: impl RepoProvider + 'static) {
+    if let Ok(mut providers) = registered_providers().write() {
+        providers.push(Arc::new(provider));
+    }
+}
+
+// Find the first provider that recognizes `repo`, and the repo id it returned
+pub fn detect_provider(repo: &str, gh_token: String) -> Option<(Arc<dyn RepoProvider>, String)> {
+    if get_repo_source() == RepoSource::Git {
+        let provider: Arc<dyn RepoProvider> = Arc::new(GitProvider);
+        return provider.detect(repo).map(|id| (provider, id));
+    }
+
+    let registered = registered_providers()
+        .read()
+        .map(|providers| providers.clone())
+        .unwrap_or_default();
+
+    // GitHub comes last, as it accepts "owner/repo" paths
+    let built_in: Vec<Arc<dyn RepoProvider>> = vec![
+        Arc::new(GitLabProvider),
+        Arc::new(GiteaProvider),
+        Arc::new(GitHubProvider::new(gh_token)),
+    ];
+
+    registered
+        .into_iter()
+        .chain(built_in)
+        .find_map(|provider| provider.detect(repo).map(|id| (provider, id)))
+}
+
+#[derive(Serialize, Debug)]
+pub struct RepoScoreInfo {
+    subscribers: f64,
+    contributors_up_to_100: usize,
+    commits_from_upto_100_contributors: f64,
+    secondary_contribution_pct: usize,
+    tertiary_contribution_pct: usize,
+    merged_pull_requests_in_last_100: usize,
+    months_since_last_pr_merged: f64,
+    months_since_last_issue_closed: f64,
+    push_span_in_months: f64,
+    months_since_last_push: f64,
+    // Only from git history
+    authors: usize,
+    bus_factor: usize,
+    commits_in_last_12_months: usize,
+    tagged_releases_pct: usize,
+    #[serde(skip)]
+    from_git: bool,
+    // Factors that couldn't be computed (e.g. no PRs in git history)
+    unavailable: Vec<&'static str>,
+}
+
+impl RepoScoreInfo {
+    // `crate_versions` are the non-yanked versions of the crate `crate_id` using the repo, if any
+    fn from_repo_info(repo_info: &RepoInfo, crate_id: &str, crate_versions: &[String]) -> Result<Self> {
+        let general_info = &repo_info.general_info;
+        let mut unavailable = Vec::new();
+
+        // Days active, months since last push
+        let push_span_in_months =esr_util::span_in_months(&general_info.created_at,
+                                                          &general_info.pushed_at)?;
+        let months_since_last_push = esr_util::age_in_months(&general_info.pushed_at)?;
+
+        // Get subscribers count
+        let subscribers = general_info.subscribers_count as f64;
+
+        // Get contributor count, commit count using contributors info.
+        if repo_info.top_100_contributors.is_empty() {
+            Err("Empty contributors list")?;
+        }
+
+        let contributors_up_to_100 = repo_info.top_100_contributors.len();
+
+        let commits_from_upto_100_contributors = repo_info.top_100_contributors
+            .iter()
+            .map(|contributor| contributor.contributions)
+            .sum::<usize>() as f64;
+
+        // Secondary and Tertiary contribution pct
+        let commits = commits_from_upto_100_contributors;
+        let top_committer_contrib = repo_info.top_100_contributors[0].contributions as f64 / commits;
+        let second_committer_contrib = repo_info.top_100_contributors.get(1)
+            .map(|c| c.contributions as f64 / commits)
+            .unwrap_or(0_f64);
+
+
+        let secondary_contribution_pct = ((1.0 - top_committer_contrib) * 100.0).ceil() as usize;
+        let tertiary_contribution_pct = ((1.0 - top_committer_contrib - second_committer_contrib) * 100.0).ceil() as usize;
+
+        // merged pull requests in last 100, months since last merged
+        let merged_pull_requests_in_last_100 = repo_info.last_100_pull_requests
+            .iter()
+            .filter(|pr| pr.merged_at.is_some())
+            .count();
+
+        let last_pr_merged_opt = repo_info.last_100_pull_requests
+            .iter()
+            .filter(|pr| pr.merged_at.is_some())
+            .nth(0);
+
+        let months_since_last_pr_merged = match last_pr_merged_opt {
+            Some(pr) => esr_util::age_in_months(pr.merged_at.as_ref().ok_or("Impossible")?)?,
+            None => esr_util::age_in_months(&general_info.created_at)?,
+        };
+
+        // months since last closed
+        let last_issue_closed_opt = repo_info.last_100_closed_issues.get(0);
+
+        let months_since_last_issue_closed = match last_issue_closed_opt {
+            Some(issue) => esr_util::age_in_months(issue.closed_at.as_ref().ok_or("Impossible")?)?,
+            None => esr_util::age_in_months(&general_info.created_at)?,
+        };
+
+        // Git history only has commits and tags
+        let (authors, bus_factor, commits_in_last_12_months, tagged_releases_pct) = match repo_info.git_stats {
+            Some(ref stats) => {
+                unavailable.extend(&["subscribers",
+                                     "merged_pull_requests_in_last_100",
+                                     "months_since_last_pr_merged",
+                                     "months_since_last_issue_closed"]);
+                if crate_versions.is_empty() {
+                    unavailable.push("tagged_releases_pct");
+                }
+
+                (stats.get_authors(),
+                 stats.get_bus_factor(),
+                 stats.get_commits_in_last_12_months(),
+                 stats.tagged_versions_pct(crate_id, crate_versions))
+            },
+            None => (0, 0, 0, 0),
+        };
+
+        // Done
+        Ok(Self {
+            subscribers,
+            contributors_up_to_100,
+            commits_from_upto_100_contributors,
+            secondary_contribution_pct,
+            tertiary_contribution_pct,
+            push_span_in_months,
+            merged_pull_requests_in_last_100,
+            months_since_last_pr_merged,
+            months_since_last_issue_closed,
+            months_since_last_push,
+            authors,
+            bus_factor,
+            commits_in_last_12_months,
+            tagged_releases_pct,
+            from_git: repo_info.git_stats.is_some(),
+            unavailable,
+        })
+    }
+
+    fn mk_score(&self, weights: &Weights) -> (Vec<ScoreRow>, f64, f64) {
+        let mut positive_score = 0.0;
+        let mut negative_score = 0.0;
+        let mut table = Vec::with_capacity(14);
+        let factors = weights.repo_factors();
+        let unavailable = &self.unavailable;
+
+        // +ve
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.subscribers);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.contributors_up_to_100);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.commits_from_upto_100_contributors);
+
+        // We only take secondary/tertiary contribution into account if the repo has >= 50 commits
+        if self.commits_from_upto_100_contributors >= 50.0 {
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.secondary_contribution_pct);
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.tertiary_contribution_pct);
+        }
+
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.push_span_in_months);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.merged_pull_requests_in_last_100);
+
+        if self.from_git {
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.authors);
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.bus_factor);
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.commits_in_last_12_months);
+            score_add!(table, positive_score, negative_score, factors, unavailable, self.tagged_releases_pct);
+        }
+
+        // -ve
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.months_since_last_pr_merged);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.months_since_last_issue_closed);
+        score_add!(table, positive_score, negative_score, factors, unavailable, self.months_since_last_push);
+
+        (table, positive_score, negative_score)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RepoInfoWithScore {
+    provider: String,
+    #[serde(rename = "info")]
+    repo_info: RepoInfo,
+    #[serde(rename = "factors")]
+    repo_score_info: RepoScoreInfo,
+    weights_profile: String,
+    #[serde(rename = "positive")]
+    score_positive: f64,
+    #[serde(rename = "negative")]
+    score_negative: f64,
+    #[serde(rename = "table")]
+    score_table: Vec<ScoreRow>,
+}
+
+impl RepoInfoWithScore {
+    pub async fn from_provider(provider: Arc<dyn RepoProvider>,
+                               id: String,
+                               crate_id: String,
+                               crate_versions: Vec<String>) -> Result<Self> {
+        let repo_info = provider.fetch(id).await?;
+        Self::from_info(provider.name(), repo_info, &crate_id, &crate_versions)
+    }
+
+    pub fn from_info(provider: &str, repo_info: RepoInfo, crate_id: &str, crate_versions: &[String]) -> Result<Self> {
+        let repo_score_info = RepoScoreInfo::from_repo_info(&repo_info, crate_id, crate_versions)?;
+        let weights = esr_weights::get_weights();
+        let (score_table, score_positive, score_negative) = repo_score_info.mk_score(weights);
+
+        Ok(Self {
+            provider: provider.into(),
+            repo_info,
+            repo_score_info,
+            weights_profile: weights.get_name().into(),
+            score_positive,
+            score_negative,
+            score_table,
+        })
+    }
+
+    pub fn get_provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn get_info(&self) -> &RepoInfo {
+        &self.repo_info
+    }
+
+    pub fn get_score_info(&self) -> &RepoScoreInfo {
+        &self.repo_score_info
+    }
+
+    pub fn get_weights_profile(&self) -> &str {
+        &self.weights_profile
+    }
+
+    pub fn get_score_tuple(&self) -> (f64, f64) {
+        (self.score_positive, self.score_negative)
+    }
+
+    pub fn get_score_table(&self) -> &[ScoreRow] {
+        &self.score_table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_weights_reproduce_baseline_score() {
+        // Factor values of mio from the README
+        let mut info = RepoScoreInfo {
+            subscribers: 104.0,
+            contributors_up_to_100: 100,
+            commits_from_upto_100_contributors: 629.0,
+            secondary_contribution_pct: 50,
+            tertiary_contribution_pct: 41,
+            merged_pull_requests_in_last_100: 78,
+            months_since_last_pr_merged: 1.739f64.powf(1.0 / 1.5),
+            months_since_last_issue_closed: 0.582f64.powf(1.0 / 1.5),
+            push_span_in_months: 45.4,
+            months_since_last_push: 0.046f64.powf(1.0 / 1.5),
+            authors: 0,
+            bus_factor: 0,
+            commits_in_last_12_months: 0,
+            tagged_releases_pct: 0,
+            from_git: false,
+            unavailable: Vec::new(),
+        };
+
+        let (table, pos, neg) = info.mk_score(&Weights::default());
+        assert!((pos - 990.434).abs() < 1e-2, "{}", pos);
+        assert!((neg - -2.505).abs() < 1e-2, "{}", neg);
+        assert_eq!(table.len(), 10);
+
+        // Secondary/tertiary contribution only count with 50+ commits
+        info.commits_from_upto_100_contributors = 49.0;
+        let (table, _, _) = info.mk_score(&Weights::default());
+        assert_eq!(table.len(), 8);
+    }
+}
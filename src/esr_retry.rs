@@ -0,0 +1,227 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use serde::Deserialize;
+use once_cell::sync::OnceCell;
+use smol::Task;
+
+use crate::esr_errors::EsrError;
+
+// How failed requests are retried. Set in the `[retry]` section of the config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct RetryPolicy {
+    // Attempts per request, including the first one. 1 disables retrying.
+    attempts: u32,
+    // Backoff before the 2nd attempt, doubled for each attempt after it
+    backoff_ms: u64,
+    max_backoff_ms: u64,
+    // Backoffs are randomly shortened or lengthened by up to this fraction
+    // (0.0-1.0), so concurrent requests don't retry all at once
+    jitter: f64,
+    // Retryable HTTP statuses
+    statuses: Vec<u16>,
+    // Retry connection failures and timeouts
+    network_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff_ms: 500,
+            max_backoff_ms: 8000,
+            jitter: 0.5,
+            statuses: vec![500, 502, 503, 504],
+            network_errors: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts.max(1)
+    }
+
+    pub fn is_retryable(&self, e: &EsrError) -> bool {
+        match *e {
+            EsrError::ClientError(status, ..) | EsrError::ServerError(status, ..) => self.statuses.contains(&status),
+            EsrError::Isahc(ref e) => self.network_errors && (e.is_network() || e.is_timeout()),
+            _ => false,
+        }
+    }
+
+    // Backoff before attempt no. `attempt` (starting at 2)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(2).min(16);
+        let backoff = self.backoff_ms.saturating_mul(1 << exp).min(self.max_backoff_ms) as f64;
+
+        let jitter = self.jitter.max(0.0).min(1.0);
+        let factor = 1.0 - jitter + 2.0 * jitter * fastrand::f64();
+        Duration::from_millis((backoff * factor) as u64)
+    }
+}
+
+// Ids (crates or repos) requests are made for, while polling a future
+// wrapped by `scoped()`
+thread_local! {
+    static SCOPE: RefCell<Arc<Vec<String>>> = RefCell::new(Arc::new(Vec::new()));
+}
+
+fn current_scope() -> Arc<Vec<String>> {
+    SCOPE.with(|scope| scope.borrow().clone())
+}
+
+// A future polled with `scope` set
+pub struct Scoped<F> {
+    scope: Arc<Vec<String>>,
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let prev = SCOPE.with(|scope| scope.replace(self.scope.clone()));
+        let res = self.fut.as_mut().poll(cx);
+        SCOPE.with(|scope| scope.replace(prev));
+        res
+    }
+}
+
+// Attribute retries of requests made by `fut` to `ids`
+pub fn scoped<F: Future>(ids: Vec<String>, fut: F) -> Scoped<F> {
+    Scoped { scope: Arc::new(ids), fut: Box::pin(fut) }
+}
+
+// `smol::spawn()`, with retries attributed to the ids of the spawning task
+pub fn spawn<T: Send + 'static>(fut: impl Future<Output = T> + Send + 'static) -> Task<T> {
+    smol::spawn(Scoped { scope: current_scope(), fut: Box::pin(fut) })
+}
+
+// Ids of the current task, for work done on behalf of several tasks
+pub(crate) fn current_ids() -> Vec<String> {
+    current_scope().to_vec()
+}
+
+fn get_retried() -> &'static Mutex<BTreeMap<String, u32>> {
+    static RETRIED: OnceCell<Mutex<BTreeMap<String, u32>>> = OnceCell::new();
+    RETRIED.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+// Count a retry against the ids of the current task
+pub(crate) fn record_retry() {
+    let scope = current_scope();
+    if let Ok(mut retried) = get_retried().lock() {
+        for id in scope.iter() {
+            *retried.entry(id.clone()).or_insert(0) += 1;
+        }
+    }
+}
+
+// Requests retried while scoring `id`
+pub fn retries_of(id: &str) -> u32 {
+    get_retried().lock()
+        .map(|retried| retried.get(id).copied().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde::Deserialize;
+
+    use crate::esr_from::EsrFrom;
+    use crate::esr_test_util::{StubServer, StubResponse};
+
+    fn policy(jitter: f64, statuses: &[u16]) -> RetryPolicy {
+        RetryPolicy { jitter, statuses: statuses.to_vec(), ..RetryPolicy::default() }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let no_jitter = policy(0.0, &[]);
+        let backoffs: Vec<_> = (2..=8).map(|attempt| no_jitter.backoff(attempt).as_millis()).collect();
+        assert_eq!(backoffs, [500, 1000, 2000, 4000, 8000, 8000, 8000]);
+        // No overflow with silly attempt counts
+        assert_eq!(no_jitter.backoff(u32::MAX).as_millis(), 8000);
+    }
+
+    #[test]
+    fn backoff_jitter_range() {
+        let half = policy(0.5, &[]);
+        let backoffs: Vec<_> = (0..200).map(|_| half.backoff(3).as_millis()).collect();
+        assert!(backoffs.iter().all(|&ms| (500..=1500).contains(&ms)), "{:?}", backoffs);
+        assert!(backoffs.iter().any(|&ms| ms != backoffs[0]));
+
+        // Out of range jitter is clamped
+        let backoffs: Vec<_> = (0..200).map(|_| policy(7.0, &[]).backoff(2).as_millis()).collect();
+        assert!(backoffs.iter().all(|&ms| ms <= 1000), "{:?}", backoffs);
+        assert_eq!(policy(-1.0, &[]).backoff(2).as_millis(), 500);
+    }
+
+    #[test]
+    fn retryable_errors() {
+        let url = || "https://crates.io/api/v1/crates/foo".to_string();
+        let default = RetryPolicy::default();
+
+        assert!(default.is_retryable(&EsrError::ServerError(502, url(), None)));
+        assert!(!default.is_retryable(&EsrError::ServerError(501, url(), None)));
+        assert!(!default.is_retryable(&EsrError::ClientError(429, url(), None)));
+        assert!(!default.is_retryable(&EsrError::NotFound(url(), None)));
+        assert!(!default.is_retryable(&EsrError::RateLimited("crates.io".into(), 60)));
+        assert!(!default.is_retryable(&EsrError::Other("oops".into())));
+
+        let with_429 = policy(0.5, &[429]);
+        assert!(with_429.is_retryable(&EsrError::ClientError(429, url(), None)));
+        assert!(!with_429.is_retryable(&EsrError::ServerError(502, url(), None)));
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Pong {
+        pong: bool,
+    }
+
+    impl EsrFrom for Pong {}
+
+    #[test]
+    fn retried_502_is_counted_against_scope() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let server = StubServer::start(move |_| match server_hits.fetch_add(1, Ordering::SeqCst) {
+            0 => StubResponse::json(502, r#"{"message": "Bad Gateway"}"#),
+            _ => StubResponse::json(200, r#"{"pong": true}"#),
+        });
+        let url = format!("{}/api/v1/crates/retried", server.url());
+
+        // Requests of spawned tasks count against the scope they were spawned in
+        let pong = smol::block_on(scoped(vec!["retried".into()], async move {
+            spawn(async move { Pong::from_url(&url).await }).await
+        })).unwrap();
+
+        assert!(pong.pong);
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(retries_of("retried"), 1);
+        assert_eq!(retries_of("other"), 0);
+    }
+}
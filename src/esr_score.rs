@@ -9,17 +9,221 @@
     file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
 */
 
-use crate::esr_crate::{CrateInfoWithScore, CrateInfo, CrateGeneralInfo};
+use crate::esr_crate::{CrateInfoWithScore, CrateInfo, CrateGeneralInfo, Owner};
+use crate::esr_deps::LocalDep;
 use crate::esr_from::EsrFrom;
-use crate::esr_github::RepoInfoWithScore;
+use crate::esr_repo::{self, RepoInfoWithScore};
+use crate::esr_history;
+use crate::esr_retry;
 use crate::esr_printer::EsrPrinter;
-use crate::esr_util;
-use crate::esr_errors::Result;
+use crate::esr_errors::{Result, EsrError};
 
+use serde::Serialize;
+use smol::Task;
 use term_string::TermString;
 
 use std::f64;
 use std::default::Default;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScoreRow {
+    pub factor: String,
+    pub value: f64,
+    pub exponent: f64,
+    pub weight: f64,
+    pub contribution: f64,
+    // False if the factor couldn't be computed (e.g. offline)
+    pub available: bool,
+    // Counts are shown without decimals in score details
+    #[serde(skip)]
+    pub count: bool,
+}
+
+// Whether factor values of a type are counts
+pub trait FactorValue: Copy {
+    const IS_COUNT: bool;
+}
+
+impl FactorValue for usize {
+    const IS_COUNT: bool = true;
+}
+
+impl FactorValue for f64 {
+    const IS_COUNT: bool = false;
+}
+
+pub fn is_count<T: FactorValue>(_: T) -> bool {
+    T::IS_COUNT
+}
+
+// A row of a comparison table, with a cell per compared crate
+#[derive(Debug, Clone)]
+pub struct CompareRow {
+    pub label: String,
+    // None if not available for a crate
+    pub cells: Vec<Option<String>>,
+    // Indices of the best cells. Empty if there is no clear winner.
+    pub winners: Vec<usize>,
+}
+
+impl CompareRow {
+    fn new(label: &str, cells: Vec<Option<String>>) -> Self {
+        Self { label: label.into(), cells, winners: Vec::new() }
+    }
+
+    // The cells with the highest value win, unless all cells are equal
+    fn with_winners(label: &str, cells: Vec<Option<(String, f64)>>) -> Self {
+        let max = cells.iter()
+            .filter_map(|cell| cell.as_ref().map(|&(_, val)| val))
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let mut winners: Vec<_> = cells.iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.as_ref().map(|&(_, val)| (val - max).abs() < 1e-9).unwrap_or(false))
+            .map(|(i, _)| i)
+            .collect();
+
+        if winners.len() == cells.len() {
+            winners.clear();
+        }
+
+        Self {
+            label: label.into(),
+            cells: cells.into_iter().map(|cell| cell.map(|(s, _)| s)).collect(),
+            winners,
+        }
+    }
+}
+
+// What search results are ranked by. Everything but `Score` comes with the
+// search response itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Score,
+    Downloads,
+    RecentDownloads,
+    ExactMatch,
+}
+
+impl SortBy {
+    // Ties are broken by score
+    fn key(self, cr: &CrateGeneralInfo) -> f64 {
+        match self {
+            SortBy::Score => 0.0,
+            SortBy::Downloads => cr.get_downloads() as f64,
+            SortBy::RecentDownloads => cr.get_recent_downloads().unwrap_or(0) as f64,
+            SortBy::ExactMatch => if cr.is_exact_match() { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+// No release in this many months
+pub const STALE_MONTHS: f64 = 12.0;
+
+// Summary of all crates of an owner
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct OwnerPortfolio {
+    crates: usize,
+    // Crates with crate scores
+    scored: usize,
+    dependants_from_non_owners: usize,
+    // Empty/all yanked, or no release in STALE_MONTHS
+    stale: usize,
+    stale_pct: f64,
+}
+
+impl OwnerPortfolio {
+    pub fn from_results(results: &[(String, Result<Scores>)]) -> Self {
+        let mut portfolio = Self { crates: results.len(), ..Self::default() };
+
+        let cr_scores = results.iter().filter_map(|(_, res)| res.as_ref().ok()?.get_crate_score());
+        for cr_score in cr_scores {
+            let score_info = cr_score.get_score_info();
+            portfolio.scored += 1;
+
+            if score_info.is_available("dependants_from_non_owners") {
+                portfolio.dependants_from_non_owners += score_info.get_dependants_from_non_owners();
+            }
+
+            let stale = cr_score.get_info().empty_or_all_yanked()
+                || (score_info.is_available("months_since_last_release")
+                    && score_info.get_months_since_last_release() > STALE_MONTHS);
+            if stale {
+                portfolio.stale += 1;
+            }
+        }
+
+        if portfolio.scored > 0 {
+            portfolio.stale_pct = portfolio.stale as f64 * 100.0 / portfolio.scored as f64;
+        }
+
+        portfolio
+    }
+
+    pub fn get_crates(&self) -> usize {
+        self.crates
+    }
+
+    pub fn get_scored(&self) -> usize {
+        self.scored
+    }
+
+    pub fn get_dependants_from_non_owners(&self) -> usize {
+        self.dependants_from_non_owners
+    }
+
+    pub fn get_stale(&self) -> usize {
+        self.stale
+    }
+
+    pub fn get_stale_pct(&self) -> f64 {
+        self.stale_pct
+    }
+}
+
+#[derive(Serialize)]
+struct OwnerJson<'a> {
+    owner: &'a Owner,
+    portfolio: OwnerPortfolio,
+    crates: Vec<ResultJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct CompareJson<'a> {
+    crates: Vec<ResultJson<'a>>,
+    // row => winning crates
+    winners: BTreeMap<String, Vec<&'a str>>,
+}
+
+#[derive(Serialize)]
+struct ScoresJson<'a> {
+    id: &'a str,
+    #[serde(rename = "crate")]
+    crate_score: Option<&'a CrateInfoWithScore>,
+    repo: Option<&'a RepoInfoWithScore>,
+    repo_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorJson<'a> {
+    id: &'a str,
+    error: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ResultJson<'a> {
+    Ok(ScoresJson<'a>),
+    Err(ErrorJson<'a>),
+}
+
+#[derive(Serialize)]
+struct DepResultJson<'a> {
+    dependency: &'a LocalDep,
+    #[serde(flatten)]
+    result: ResultJson<'a>,
+}
 
 pub enum Scores {
     CrateAndRepo(CrateInfoWithScore, Result<RepoInfoWithScore>),
@@ -28,39 +232,70 @@ pub enum Scores {
 }
 
 impl Scores {
+    // Spawn getting the repo score, using the first provider that recognizes `repo`
+    fn spawn_repo_score(repo: &str, gh_token: String, crate_id: String, crate_versions: Vec<String>)
+                        -> Option<Task<Result<RepoInfoWithScore>>> {
+        esr_repo::detect_provider(repo, gh_token)
+            .map(|(provider, id)| esr_retry::spawn(RepoInfoWithScore::from_provider(provider, id, crate_id, crate_versions)))
+    }
+
+    // Retries of requests made while scoring are counted against `id`
     pub async fn from_id_with_token(id: String, gh_token: String) -> Result<Self> {
-        let cr_info = CrateInfo::from_id(&*id).await?;
+        esr_retry::scoped(vec![id.clone()], async move {
+            let cr_info = CrateInfo::from_id(&*id).await?;
 
-        let repo_score_res = cr_info.github_id()
-            .ok_or("Failed to get GitHub id")
-            .map(|gh_id| smol::spawn(RepoInfoWithScore::from_id_with_token(gh_id, gh_token)));
+            let repo_score_opt = cr_info.get_repository()
+                .and_then(|repo| Self::spawn_repo_score(repo, gh_token, id.clone(), cr_info.non_yanked_versions()));
 
-        let cr_score = CrateInfoWithScore::from_info(cr_info).await?;
+            let cr_score = CrateInfoWithScore::from_info(cr_info).await?;
 
-        match repo_score_res {
-            Ok(repo_score) => Ok(Scores::CrateAndRepo(cr_score, repo_score.await)),
-            Err(_) => Ok(Scores::CrateOnly(cr_score)),
-        }
+            let scores = match repo_score_opt {
+                Some(repo_score) => Scores::CrateAndRepo(cr_score, repo_score.await),
+                None => Scores::CrateOnly(cr_score),
+            };
+
+            Ok(scores.recorded(&id).await)
+        }).await
     }
 
     pub async fn from_id_crate_only(id: String) -> Result<Self> {
-        let cr_score = CrateInfoWithScore::from_id(id).await?;
-        Ok(Scores::CrateOnly(cr_score))
+        esr_retry::scoped(vec![id.clone()], async move {
+            let cr_score = CrateInfoWithScore::from_id(id.clone()).await?;
+            Ok(Scores::CrateOnly(cr_score).recorded(&id).await)
+        }).await
     }
 
     pub async fn from_id_with_token_repo_only(id: String, gh_token: String) -> Result<Self> {
-        let cr_score = CrateInfoWithScore::from_id(id).await?;
-        let gh_id = cr_score.get_info().github_id().ok_or("repo-only score requested but failed to get GitHub id")?;
-        let repo_score = RepoInfoWithScore::from_id_with_token(gh_id, gh_token).await?;
-
-        Ok(Scores::RepoOnly(repo_score))
+        esr_retry::scoped(vec![id.clone()], async move {
+            let cr_score = CrateInfoWithScore::from_id(id.clone()).await?;
+            let cr_info = cr_score.get_info();
+            let repo_score = cr_info.get_repository()
+                .and_then(|repo| Self::spawn_repo_score(repo, gh_token, id.clone(), cr_info.non_yanked_versions()))
+                .ok_or("repo-only score requested but failed to get a supported repository")?
+                .await?;
+
+            Ok(Scores::RepoOnly(repo_score).recorded(&id).await)
+        }).await
     }
 
     pub async fn from_repo_with_token(repo: String, gh_token: String) -> Result<Self> {
-        let gh_id = esr_util::github_repo(&*repo).ok_or("repo score requested but failed to get a valid GitHub repo path")?;
-        let repo_score = RepoInfoWithScore::from_id_with_token(gh_id, gh_token).await?;
+        esr_retry::scoped(vec![repo.clone()], async move {
+            let repo_score = Self::spawn_repo_score(&*repo, gh_token, String::new(), Vec::new())
+                .ok_or("repo score requested but no provider recognized the repo path")?
+                .await?;
 
-        Ok(Scores::RepoOnly(repo_score))
+            Ok(Scores::RepoOnly(repo_score).recorded(&repo).await)
+        }).await
+    }
+
+    // Record scores in the history DB, if enabled. Failing to record is not fatal.
+    async fn recorded(self, id: &str) -> Self {
+        if esr_history::is_recording() {
+            if let Err(ref e) = esr_history::record(id, &self).await {
+                EsrPrinter::record_failed(id, e).eprintln();
+            }
+        }
+        self
     }
 
     // ====================
@@ -85,18 +320,30 @@ impl Scores {
         }
     }
 
-    pub fn detailed_scores(&self) -> TermString {
-        // Unfortunately, `if let` is not as powerful as `match`. So, we have to
-        // to do this *_opt dance.
-        let cr_score_opt = match *self {
+    pub fn get_crate_score(&self) -> Option<&CrateInfoWithScore> {
+        match *self {
             Scores::CrateAndRepo(ref cr_score, _) | Scores::CrateOnly(ref cr_score) => Some(cr_score),
             Scores::RepoOnly(_) => None,
-        };
+        }
+    }
 
-        let repo_score_opt = match *self {
+    pub fn get_repo_score(&self) -> Option<&RepoInfoWithScore> {
+        match *self {
             Scores::CrateAndRepo(_, Ok(ref repo_score)) | Scores::RepoOnly(ref repo_score) => Some(repo_score),
             Scores::CrateAndRepo(_, Err(_)) | Scores::CrateOnly(_) => None,
-        };
+        }
+    }
+
+    pub fn get_repo_error(&self) -> Option<&EsrError> {
+        match *self {
+            Scores::CrateAndRepo(_, Err(ref e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn detailed_scores(&self) -> TermString {
+        let cr_score_opt = self.get_crate_score();
+        let repo_score_opt = self.get_repo_score();
 
         let mut ret = TermString::default();
 
@@ -105,12 +352,16 @@ impl Scores {
             ret += self.info_pair(id, false).1 + "\n";
 
             let table = cr_score.get_score_table();
-            ret += EsrPrinter::score_details("Crate Score Details", table) + "\n";
+            let title = format!("Crate Score Details (weights: {})", cr_score.get_weights_profile());
+            ret += EsrPrinter::score_details(&title, table) + "\n";
         }
 
         if let Some(repo_score) = repo_score_opt {
             let table = repo_score.get_score_table();
-            ret += EsrPrinter::score_details("Repo Score Details", table) + "\n";
+            let title = format!("Repo Score Details (provider: {}, weights: {})",
+                                repo_score.get_provider(),
+                                repo_score.get_weights_profile());
+            ret += EsrPrinter::score_details(&title, table) + "\n";
 
             // Print repo score overview if it wasn't already printed
             if cr_score_opt.is_none() {
@@ -126,24 +377,35 @@ impl Scores {
     pub async fn collect_scores(crates: &[CrateGeneralInfo], token: &str,
                           crate_only: bool,
                           repo_only: bool) -> Vec<(String, Result<Self>)> {
+        let ids: Vec<_> = crates
+            .iter()
+            .map(|cr| String::from(cr.get_id()))
+            .collect();
+
+        Self::collect_scores_from_ids(&ids, token, crate_only, repo_only).await
+    }
+
+    pub async fn collect_scores_from_ids(ids: &[String], token: &str,
+                          crate_only: bool,
+                          repo_only: bool) -> Vec<(String, Result<Self>)> {
 
         let task_iter = if crate_only {
-            crates
+            ids
                 .iter()
-                .map(|cr| String::from(cr.get_id()))
+                .cloned()
                 .map(|id| smol::spawn(async { (id.clone(), Scores::from_id_crate_only(id).await) }))
                 .collect::<Vec<_>>()
 
         } else if repo_only {
-            crates
+            ids
                 .iter()
-                .map(|cr| (String::from(cr.get_id()), String::from(token)))
+                .map(|id| (id.clone(), String::from(token)))
                 .map(|(id, token)| smol::spawn(async { (id.clone(), Scores::from_id_with_token_repo_only(id, token).await) }))
                 .collect::<Vec<_>>()
         } else {
-            crates
+            ids
                 .iter()
-                .map(|cr| (String::from(cr.get_id()), String::from(token)))
+                .map(|id| (id.clone(), String::from(token)))
                 .map(|(id, token)| smol::spawn(async { (id.clone(), Scores::from_id_with_token(id, token).await) }))
                 .collect::<Vec<_>>()
         };
@@ -151,20 +413,57 @@ impl Scores {
         futures::future::join_all(task_iter).await
     }
 
+    fn sort_score(&self, sort_positive: bool) -> f64 {
+        let (pos, neg) = match *self {
+            Scores::CrateAndRepo(ref cr_score, _) | Scores::CrateOnly(ref cr_score) => cr_score.get_score_tuple(),
+            Scores::RepoOnly(ref repo_score) => repo_score.get_score_tuple(),
+        };
+
+        match sort_positive {
+            true => pos,
+            false => pos + neg,
+        }
+    }
+
+    fn json_repr<'a>(&'a self, id: &'a str) -> ScoresJson<'a> {
+        let (crate_score, repo, repo_error) = match *self {
+            Scores::CrateAndRepo(ref cr_score, Ok(ref repo_score)) => (Some(cr_score), Some(repo_score), None),
+            Scores::CrateAndRepo(ref cr_score, Err(ref e)) => (Some(cr_score), None, Some(e.to_string())),
+            Scores::CrateOnly(ref cr_score) => (Some(cr_score), None, None),
+            Scores::RepoOnly(ref repo_score) => (None, Some(repo_score), None),
+        };
+
+        ScoresJson { id, crate_score, repo, repo_error }
+    }
+
+    pub fn detailed_scores_json(&self, id: &str) -> String {
+        serde_json::to_string_pretty(&self.json_repr(id))
+            .unwrap_or_else(|e| Self::error_json(id, &e.into()))
+    }
+
+    pub fn error_json(id: &str, e: &EsrError) -> String {
+        let err = ErrorJson { id, error: e.to_string() };
+        // Serializing two strings can't fail
+        serde_json::to_string_pretty(&err).unwrap_or_default()
+    }
+
     fn info_pair(&self, id: &str, sort_positive: bool) -> (f64, TermString) {
+        let sort_score = self.sort_score(sort_positive);
+
+        let retries_msg = match esr_retry::retries_of(id) {
+            0 => TermString::default(),
+            1 => EsrPrinter::msg_pair("Retries    ", "1 retry"),
+            retries => EsrPrinter::msg_pair("Retries    ", format!("{} retries", retries)),
+        };
+
         match *self {
             Scores::CrateAndRepo(ref cr_score, _) | Scores::CrateOnly(ref cr_score) => {
                 let cr_info = cr_score.get_info();
-                let (pos, neg) = cr_score.get_score_tuple();
 
-                let empty_or_all_yanked = match cr_info.empty_or_all_yanked() {
-                    true => EsrPrinter::all_yanked() + "\n ",
-                    false => "\n ".into(),
-                };
-
-                let sort_score = match sort_positive {
-                    true => pos,
-                    false => pos + neg,
+                let empty_or_all_yanked = match (cr_info.empty_or_all_yanked(), cr_info.is_from_index()) {
+                    (true, _) => EsrPrinter::all_yanked() + "\n ",
+                    (false, true) => EsrPrinter::from_index() + "\n ",
+                    (false, false) => "\n ".into(),
                 };
 
                 let releases = cr_score.get_score_info().get_releases();
@@ -175,16 +474,40 @@ impl Scores {
                 let releases_formatted = EsrPrinter::releases(stable, non_yanked_pre, yanked);
 
                 let max_ver = Some(cr_info.get_max_version());
-                let max_ver_age = cr_info.max_version_age();
-                let max_ver_msg = EsrPrinter::release(max_ver, max_ver_age);
-
                 let last_stable_version = cr_info.last_stable_version();
-                let last_stable_version_age = cr_info.last_stable_version_age();
-                let last_stable_version_msg = EsrPrinter::release(last_stable_version, last_stable_version_age);
+
+                // Release dates are not in the index
+                let (max_ver_msg, last_stable_version_msg) = match cr_info.is_from_index() {
+                    true => (EsrPrinter::release_undated(max_ver), EsrPrinter::release_undated(last_stable_version)),
+                    false => {
+                        let max_ver_age = cr_info.max_version_age();
+                        let last_stable_version_age = cr_info.last_stable_version_age();
+                        (EsrPrinter::release(max_ver, max_ver_age),
+                         EsrPrinter::release(last_stable_version, last_stable_version_age))
+                    },
+                };
 
                 let dependants = cr_score.get_score_info().get_dependants();
                 let d_b_n_o = cr_score.get_score_info().get_dependants_from_non_owners();
-                let dependants_msg = format!("{} ({} from non owners)", dependants, d_b_n_o);
+                let t_d = cr_score.get_score_info().get_transitive_dependants();
+                let t_d_b_n_o = cr_score.get_score_info().get_transitive_dependants_from_non_owners();
+                let dependants_msg = format!("{} ({} from non owners), {} transitive ({} from non owners)",
+                                             dependants, d_b_n_o, t_d, t_d_b_n_o);
+
+                let downloads_msg = EsrPrinter::downloads(cr_score.get_score_info().get_downloads_in_last_90_days(),
+                                                          cr_score.get_score_info().get_downloads_growth_pct()
+                                                          - cr_score.get_score_info().get_downloads_decline_pct(),
+                                                          cr_score.get_score_info().get_daily_downloads());
+
+                let categories = match cr_info.get_categories() {
+                    [] => String::from("N/A"),
+                    categories => categories.join(", "),
+                };
+
+                let keywords = match cr_info.get_keywords() {
+                    [] => String::from("N/A"),
+                    keywords => keywords.join(", "),
+                };
 
                 let desc = cr_info.get_description()
                     .map(EsrPrinter::desc)
@@ -198,41 +521,46 @@ impl Scores {
                 info_formatter += EsrPrinter::msg_pair("Max Version", max_ver_msg);
                 info_formatter += EsrPrinter::msg_pair("Last Stable", last_stable_version_msg);
                 info_formatter += EsrPrinter::msg_pair("Dependants ", dependants_msg);
+                info_formatter += EsrPrinter::msg_pair("Downloads  ", downloads_msg);
                 info_formatter += EsrPrinter::msg_pair("License    ", cr_info.get_license().unwrap_or("N/A"));
                 info_formatter += EsrPrinter::msg_pair("Repository ", cr_info.get_repository().unwrap_or("N/A"));
+                info_formatter += EsrPrinter::msg_pair("Categories ", categories);
+                info_formatter += EsrPrinter::msg_pair("Keywords   ", keywords);
                 info_formatter += EsrPrinter::msg_pair("Description", desc);
+                info_formatter += retries_msg;
 
                 (sort_score, info_formatter)
             },
-            Scores::RepoOnly(ref repo_score) => {
-                let (pos, neg) = repo_score.get_score_tuple();
-                let sort_score = match sort_positive {
-                    true => pos,
-                    false => pos + neg,
-                };
-
-                let info_formatter = EsrPrinter::id(id) + "\n " + self.score_repo();
+            Scores::RepoOnly(_) => {
+                let info_formatter = EsrPrinter::id(id) + "\n " + self.score_repo() + retries_msg;
                 (sort_score, info_formatter)
             },
         }
     }
 
-    pub fn search_results(results: &[(String, Result<Self>)], sort_positive: bool, limit: usize) -> TermString {
+    // Highest first
+    fn sort_search_results<T>(results_vec: &mut Vec<((f64, f64), T)>) {
+        results_vec.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    }
+
+    // `results` and `crates` are in the same order, as returned by `collect_scores()`
+    pub fn search_results(results: &[(String, Result<Self>)], crates: &[CrateGeneralInfo],
+                          sort_by: SortBy, sort_positive: bool, limit: usize) -> TermString {
         let mut results_vec = Vec::with_capacity(32);
-        for res in results {
+        for (res, cr) in results.iter().zip(crates) {
             match *res {
                 (ref id, Ok(ref score_info)) => {
-                    results_vec.push(score_info.info_pair(id, sort_positive));
+                    let (sort_score, info) = score_info.info_pair(id, sort_positive);
+                    results_vec.push(((sort_by.key(cr), sort_score), info));
                 },
                 (ref id, Err(ref e)) => {
-                    results_vec.push((f64::MIN, EsrPrinter::err(&format!("{}: Failed to get score info: {}.", id, e)) + "\n"));
+                    let err = EsrPrinter::err(&format!("{}: Failed to get score info: {}.", id, e)) + "\n";
+                    results_vec.push(((f64::MIN, f64::MIN), err));
                 },
             }
         }
 
-        // Negation to get scores in reverse.
-        // `* 10000.0` to not lose order accuracy after casting.
-        results_vec.sort_by_key(|&(sort_score, _)| -(sort_score * 10000.0) as i64);
+        Self::sort_search_results(&mut results_vec);
 
         let mut ret = TermString::default();
 
@@ -243,4 +571,228 @@ impl Scores {
 
         ret
     }
+
+    fn ranked_json<'a>(results: &'a [(String, Result<Self>)], crates: &[CrateGeneralInfo],
+                       sort_by: SortBy, sort_positive: bool, limit: usize) -> Vec<ResultJson<'a>> {
+        let mut results_vec: Vec<_> = results
+            .iter()
+            .zip(crates)
+            .map(|(res, cr)| match *res {
+                (ref id, Ok(ref score_info)) => ((sort_by.key(cr), score_info.sort_score(sort_positive)),
+                                                 ResultJson::Ok(score_info.json_repr(id))),
+                (ref id, Err(ref e)) => ((f64::MIN, f64::MIN), ResultJson::Err(ErrorJson { id, error: e.to_string() })),
+            })
+            .collect();
+
+        // Same order as search_results()
+        Self::sort_search_results(&mut results_vec);
+
+        results_vec
+            .into_iter()
+            .take(limit)
+            .map(|(_, res)| res)
+            .collect()
+    }
+
+    pub fn search_results_json(results: &[(String, Result<Self>)], crates: &[CrateGeneralInfo],
+                               sort_by: SortBy, sort_positive: bool, limit: usize) -> String {
+        let ranked = Self::ranked_json(results, crates, sort_by, sort_positive, limit);
+        serde_json::to_string_pretty(&ranked)
+            .unwrap_or_else(|e| Self::error_json("search", &e.into()))
+    }
+
+    // All crates of `owner` ranked, after a portfolio summary
+    pub fn owner_results(owner: &Owner, results: &[(String, Result<Self>)], crates: &[CrateGeneralInfo],
+                         sort_positive: bool) -> TermString {
+        let portfolio = OwnerPortfolio::from_results(results);
+        EsrPrinter::owner_portfolio(owner, &portfolio) + "\n"
+            + Self::search_results(results, crates, SortBy::Score, sort_positive, results.len())
+    }
+
+    pub fn owner_results_json(owner: &Owner, results: &[(String, Result<Self>)], crates: &[CrateGeneralInfo],
+                              sort_positive: bool) -> String {
+        let owner_json = OwnerJson {
+            owner,
+            portfolio: OwnerPortfolio::from_results(results),
+            crates: Self::ranked_json(results, crates, SortBy::Score, sort_positive, results.len()),
+        };
+
+        serde_json::to_string_pretty(&owner_json)
+            .unwrap_or_else(|e| Self::error_json(owner.get_login(), &e.into()))
+    }
+
+    fn dep_info_pair(&self, dep: &LocalDep, sort_positive: bool) -> (f64, TermString) {
+        let (sort_score, mut info_formatter) = self.info_pair(dep.get_name(), sort_positive);
+
+        let locked = dep.get_locked_version().unwrap_or("N/A");
+        info_formatter += EsrPrinter::msg_pair("Kind       ", dep.kinds_str());
+        info_formatter += EsrPrinter::msg_pair("Locked     ", locked);
+
+        (sort_score, info_formatter)
+    }
+
+    // Weakest dependencies first
+    pub fn deps_results(deps: &[LocalDep], results: &[(String, Result<Self>)], sort_positive: bool) -> TermString {
+        let mut results_vec = Vec::with_capacity(deps.len());
+        for (dep, res) in deps.iter().zip(results) {
+            match *res {
+                (_, Ok(ref score_info)) => {
+                    results_vec.push(score_info.dep_info_pair(dep, sort_positive));
+                },
+                (ref id, Err(ref e)) => {
+                    results_vec.push((f64::MIN, EsrPrinter::err(&format!("{} ({}): Failed to get score info: {}.", id, dep.kinds_str(), e)) + "\n"));
+                },
+            }
+        }
+
+        results_vec.sort_by_key(|&(sort_score, _)| (sort_score * 10000.0) as i64);
+
+        let mut ret = TermString::default();
+
+        for (num, result) in results_vec.iter().enumerate() {
+            ret += EsrPrinter::id(&format!("({}) ", num + 1));
+            ret += result.1.clone() + "\n";
+        }
+
+        ret
+    }
+
+    // A row per weighted factor in any of `tables`, in order of first appearance
+    fn compare_factor_rows(tables: &[Option<&[ScoreRow]>]) -> Vec<CompareRow> {
+        let mut factors: Vec<&str> = Vec::new();
+        for row in tables.iter().flatten().flat_map(|table| table.iter()) {
+            if row.weight != 0.0 && !factors.contains(&&*row.factor) {
+                factors.push(&row.factor);
+            }
+        }
+
+        factors.into_iter()
+            .map(|factor| {
+                // Contributions decide the winner, as some factors are negative
+                let cells = tables.iter()
+                    .map(|table| {
+                        (*table)?.iter()
+                            .find(|row| row.factor == factor && row.available)
+                            .map(|row| (EsrPrinter::score_value(row.value), row.contribution))
+                    })
+                    .collect();
+                CompareRow::with_winners(factor, cells)
+            })
+            .collect()
+    }
+
+    fn compare_sections(results: &[(String, Result<Self>)]) -> Vec<(String, Vec<CompareRow>)> {
+        let scores: Vec<_> = results.iter()
+            .map(|(_, res)| res.as_ref().ok())
+            .collect();
+        let cr_scores: Vec<_> = scores.iter()
+            .map(|score| score.and_then(|s| s.get_crate_score()))
+            .collect();
+        let repo_scores: Vec<_> = scores.iter()
+            .map(|score| score.and_then(|s| s.get_repo_score()))
+            .collect();
+
+        let total = |(pos, neg): (f64, f64)| (format!("{:.3}", pos + neg), pos + neg);
+        let cr_cells = |f: &dyn Fn(&CrateInfoWithScore) -> Option<String>| {
+            cr_scores.iter().map(|cr_score| cr_score.and_then(f)).collect()
+        };
+
+        let overview = vec![
+            CompareRow::with_winners("Crate Score", cr_scores.iter()
+                .map(|cr_score| cr_score.map(|s| total(s.get_score_tuple())))
+                .collect()),
+            CompareRow::with_winners("Repo Score", repo_scores.iter()
+                .map(|repo_score| repo_score.map(|s| total(s.get_score_tuple())))
+                .collect()),
+            CompareRow::new("Releases (stable+pre+yanked)", cr_cells(&|s| {
+                let info = s.get_score_info();
+                let stable = info.get_stable_releases();
+                let non_yanked = info.get_non_yanked_releases();
+                Some(format!("{}+{}+{}", stable, non_yanked - stable, info.get_releases() - non_yanked))
+            })),
+            CompareRow::new("Max Version", cr_cells(&|s| Some(s.get_info().get_max_version().into()))),
+            CompareRow::new("Last Stable", cr_cells(&|s| s.get_info().last_stable_version().map(String::from))),
+            CompareRow::new("Dependants (from non owners)", cr_cells(&|s| {
+                let info = s.get_score_info();
+                Some(format!("{} ({})", info.get_dependants(), info.get_dependants_from_non_owners()))
+            })),
+            CompareRow::new("License", cr_cells(&|s| s.get_info().get_license().map(String::from))),
+            CompareRow::new("Repository", cr_cells(&|s| s.get_info().get_repository().map(String::from))),
+        ];
+
+        let cr_tables: Vec<_> = cr_scores.iter().map(|s| s.map(|s| s.get_score_table())).collect();
+        let repo_tables: Vec<_> = repo_scores.iter().map(|s| s.map(|s| s.get_score_table())).collect();
+
+        let mut sections = vec![(String::from("Overview"), overview)];
+        if cr_tables.iter().any(Option::is_some) {
+            sections.push(("Crate Score Factors".into(), Self::compare_factor_rows(&cr_tables)));
+        }
+        if repo_tables.iter().any(Option::is_some) {
+            sections.push(("Repo Score Factors".into(), Self::compare_factor_rows(&repo_tables)));
+        }
+        sections
+    }
+
+    // One table with a column per crate, in the order given
+    pub fn compare_results(results: &[(String, Result<Self>)]) -> TermString {
+        let ids: Vec<_> = results.iter().map(|(id, _)| &**id).collect();
+        let mut ret = EsrPrinter::compare_table(&ids, &Self::compare_sections(results));
+
+        for res in results {
+            if let (ref id, Err(ref e)) = *res {
+                ret += EsrPrinter::err(&format!("{}: Failed to get score info: {}.", id, e)) + "\n";
+            }
+        }
+
+        ret
+    }
+
+    pub fn compare_results_json(results: &[(String, Result<Self>)]) -> String {
+        let crates = results
+            .iter()
+            .map(|res| match *res {
+                (ref id, Ok(ref score_info)) => ResultJson::Ok(score_info.json_repr(id)),
+                (ref id, Err(ref e)) => ResultJson::Err(ErrorJson { id, error: e.to_string() }),
+            })
+            .collect();
+
+        let mut winners = BTreeMap::new();
+        for (_, rows) in Self::compare_sections(results) {
+            for row in rows.iter().filter(|row| !row.winners.is_empty()) {
+                let ids = row.winners.iter().map(|&i| &*results[i].0).collect();
+                winners.insert(row.label.clone(), ids);
+            }
+        }
+
+        serde_json::to_string_pretty(&CompareJson { crates, winners })
+            .unwrap_or_else(|e| Self::error_json("compare", &e.into()))
+    }
+
+    pub fn deps_results_json(deps: &[LocalDep], results: &[(String, Result<Self>)], sort_positive: bool) -> String {
+        let mut results_vec: Vec<_> = deps
+            .iter()
+            .zip(results)
+            .map(|(dependency, res)| match *res {
+                (ref id, Ok(ref score_info)) => {
+                    let result = ResultJson::Ok(score_info.json_repr(id));
+                    (score_info.sort_score(sort_positive), DepResultJson { dependency, result })
+                },
+                (ref id, Err(ref e)) => {
+                    let result = ResultJson::Err(ErrorJson { id, error: e.to_string() });
+                    (f64::MIN, DepResultJson { dependency, result })
+                },
+            })
+            .collect();
+
+        // Same order as deps_results()
+        results_vec.sort_by_key(|&(sort_score, _)| (sort_score * 10000.0) as i64);
+
+        let ranked: Vec<_> = results_vec
+            .into_iter()
+            .map(|(_, res)| res)
+            .collect();
+
+        serde_json::to_string_pretty(&ranked)
+            .unwrap_or_else(|e| Self::error_json("deps", &e.into()))
+    }
 }
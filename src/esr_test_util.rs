@@ -0,0 +1,139 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+// A local HTTP server serving canned responses to tests
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::esr_cache::{self, CacheMode};
+
+#[derive(Debug, Clone)]
+pub(crate) struct StubRequest {
+    pub method: String,
+    // Path and query (e.g. "/api/v1/crates?q=foo")
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: String,
+}
+
+pub(crate) struct StubResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl StubResponse {
+    pub fn json(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+pub(crate) struct StubServer {
+    url: String,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl StubServer {
+    // Serve requests with `handler` on a free port, until the tests exit
+    pub fn start(handler: impl Fn(&StubRequest) -> StubResponse + Send + 'static) -> Self {
+        // Stub responses must not be cached, or served from the user's cache
+        assert_eq!(esr_cache::get_cache_mode(), CacheMode::Disabled, "Tests must not set a cache mode");
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind stub server");
+        let url = format!("http://{}", listener.local_addr().expect("Failed to get stub server address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                if let Ok(request) = read_request(&stream) {
+                    let response = handler(&request);
+                    recorded.lock().expect("Stub server requests poisoned").push(request);
+                    let _ = write_response(&mut stream, &response);
+                }
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    // e.g. "http://127.0.0.1:40000"
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Requests served so far, oldest first
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().expect("Stub server requests poisoned").clone()
+    }
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<StubRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+
+        let mut header = line.splitn(2, ':');
+        let (name, value) = (header.next().unwrap_or_default(), header.next().unwrap_or_default().trim());
+        match &*name.to_ascii_lowercase() {
+            "content-length" => content_length = value.parse().unwrap_or(0),
+            "authorization" => authorization = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(StubRequest { method, path, authorization, body: String::from_utf8_lossy(&body).into() })
+}
+
+fn write_response(stream: &mut TcpStream, response: &StubResponse) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n",
+                           response.status, response.body.len());
+    for (name, value) in &response.headers {
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    head += "\r\n";
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
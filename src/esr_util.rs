@@ -18,6 +18,70 @@ pub(crate) fn crate_to_iso8601(cr_date: &str) -> String {
     }
 }
 
+// Get ISO 8601-formatted UTC string from other API dates (e.g. GitLab's or
+// Gitea's), dropping fractions of seconds.
+pub fn to_iso8601(date: &str) -> String {
+    // "YYYY-MM-DDTHH:MM:SS"
+    if date.len() < 19 || !date.is_ascii() {
+        return date.into();
+    }
+
+    let (date_time, rest) = date.split_at(19);
+    let offset = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+
+    // "+HH:MM" or "-HH:MM"
+    let offset_secs = match offset.as_bytes().get(0) {
+        Some(&sign) if (sign == b'+' || sign == b'-') && offset.len() >= 6 => {
+            let hours: i64 = offset[1..3].parse().unwrap_or(0);
+            let minutes: i64 = offset[4..6].parse().unwrap_or(0);
+            match sign {
+                b'-' => -(hours * 3600 + minutes * 60),
+                _ => hours * 3600 + minutes * 60,
+            }
+        },
+        _ => 0,
+    };
+
+    if offset_secs == 0 {
+        return String::from(date_time) + "Z";
+    }
+
+    match time::strptime(date_time, "%FT%T") {
+        Ok(tm) => {
+            let utc = time::at_utc(tm.to_timespec() - time::Duration::seconds(offset_secs));
+            match utc.strftime("%FT%TZ") {
+                Ok(utc_str) => utc_str.to_string(),
+                Err(_) => date.into(),
+            }
+        },
+        Err(_) => date.into(),
+    }
+}
+
+pub(crate) fn timestamp_to_iso8601(secs: i64) -> String {
+    let tm = time::at_utc(time::Timespec::new(secs, 0));
+    match tm.strftime("%FT%TZ") {
+        Ok(tm_str) => tm_str.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+// "host[:port]" from "scheme://host[:port]/..."
+pub(crate) fn url_host(url: &str) -> Option<&str> {
+    let rest = &url[url.find("://")? + 3..];
+    Some(rest.split('/').next().unwrap_or(rest))
+}
+
+// Percent-encode all but unreserved characters, for use in query params
+pub(crate) fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 fn date_sec(date: &str) -> Result<f64> {
     let date_tm = time::strptime(date, "%FT%TZ")?;
     Ok(date_tm.to_timespec().sec as f64)
@@ -56,3 +120,29 @@ pub(crate) fn github_repo(repo: &str) -> Option<String> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::to_iso8601;
+
+    #[test]
+    fn to_iso8601_utc() {
+        assert_eq!(to_iso8601("2021-03-01T10:20:30Z"), "2021-03-01T10:20:30Z");
+        assert_eq!(to_iso8601("2021-03-01T10:20:30+00:00"), "2021-03-01T10:20:30Z");
+    }
+
+    #[test]
+    fn to_iso8601_offsets() {
+        assert_eq!(to_iso8601("2021-03-01T10:20:30+02:30"), "2021-03-01T07:50:30Z");
+        assert_eq!(to_iso8601("2021-03-01T22:20:30-05:00"), "2021-03-02T03:20:30Z");
+        // Across a year boundary
+        assert_eq!(to_iso8601("2021-01-01T01:00:00+03:00"), "2020-12-31T22:00:00Z");
+    }
+
+    #[test]
+    fn to_iso8601_fractions() {
+        assert_eq!(to_iso8601("2021-03-01T10:20:30.123Z"), "2021-03-01T10:20:30Z");
+        assert_eq!(to_iso8601("2021-03-01T10:20:30.123456+01:00"), "2021-03-01T09:20:30Z");
+        assert_eq!(to_iso8601("2021-03-01T10:20:30.5-01:00"), "2021-03-01T11:20:30Z");
+    }
+}
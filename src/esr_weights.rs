@@ -0,0 +1,227 @@
+/*
+    This file is a part of cargo-esr.
+
+    Copyright (C) 2017 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/rust-alt/cargo-esr
+
+    This Source Code Form is subject to the terms of the Mozilla Public
+    License, v. 2.0. If a copy of the MPL was not distributed with this
+    file, You can obtain one at <http://mozilla.org/MPL/2.0/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use once_cell::sync::OnceCell;
+
+use crate::esr_errors::{Result, EsrError};
+
+// (factor, weight, exponent)
+const DEFAULT_CRATE_FACTORS: &[(&str, f64, f64)] = &[
+    // +ve
+    ("has_desc", 5.0, 1.0),
+    ("has_license", 5.0, 1.0),
+    ("has_docs", 15.0, 1.0),
+    ("activity_span_in_months", 6.0, 0.5),
+    ("releases", 0.5, 1.0),
+    ("non_yanked_releases", 0.5, 1.0),
+    ("stable_releases", 0.5, 1.0),
+    ("last_2_non_yanked_releases_downloads", 0.1, 0.5),
+    ("dependants", 0.5, 1.0),
+    ("hard_dependants", 0.75, 1.0),
+    ("dependants_on_current_versions", 0.75, 1.0),
+    ("dependants_from_non_owners", 2.5, 1.0),
+    // +ve, off by default to keep default scores comparable across versions
+    ("downloads_in_last_90_days", 0.0, 0.5),
+    ("downloads_growth_pct", 0.0, 1.0),
+    ("recent_downloads", 0.0, 0.5),
+    ("transitive_dependants", 0.0, 0.5),
+    ("transitive_dependants_from_non_owners", 0.0, 0.5),
+    // -ve
+    ("months_since_last_release", -2.0, 1.5),
+    ("empty_or_all_yanked", -5000.0, 1.0),
+    // -ve, off by default
+    ("downloads_decline_pct", 0.0, 1.0),
+];
+
+const DEFAULT_REPO_FACTORS: &[(&str, f64, f64)] = &[
+    // +ve
+    ("subscribers", 8.0, 0.5),
+    ("contributors_up_to_100", 3.0, 1.0),
+    ("commits_from_upto_100_contributors", 2.0, 0.5),
+    ("secondary_contribution_pct", 2.5, 1.0),
+    ("tertiary_contribution_pct", 5.0, 1.0),
+    ("push_span_in_months", 5.0, 0.5),
+    ("merged_pull_requests_in_last_100", 2.5, 1.0),
+    // +ve, only with `--repo-source git`
+    ("authors", 1.0, 0.5),
+    ("bus_factor", 5.0, 1.0),
+    ("commits_in_last_12_months", 1.0, 0.5),
+    ("tagged_releases_pct", 0.5, 1.0),
+    // -ve
+    ("months_since_last_pr_merged", -1.0, 1.5),
+    ("months_since_last_issue_closed", -1.0, 1.5),
+    ("months_since_last_push", -4.0, 1.5),
+];
+
+static WEIGHTS: OnceCell<Weights> = OnceCell::new();
+
+// Get the active weights profile. The built-in default profile is used if
+// `set_weights()` was never called.
+pub fn get_weights() -> &'static Weights {
+    WEIGHTS.get_or_init(Weights::default)
+}
+
+// Set the active weights profile. This can only be done once, and before any
+// scores are calculated.
+pub fn set_weights(weights: Weights) -> Result<()> {
+    WEIGHTS.set(weights)
+        .map_err(|_| EsrError::from("Weights profile already set"))
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct FactorWeight {
+    pub weight: f64,
+    pub exponent: f64,
+    pub enabled: bool,
+}
+
+impl FactorWeight {
+    fn new(weight: f64, exponent: f64) -> Self {
+        Self { weight, exponent, enabled: true }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct FactorOverride {
+    weight: Option<f64>,
+    exponent: Option<f64>,
+    enabled: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct WeightsFile {
+    name: Option<String>,
+    #[serde(rename = "crate", default)]
+    crate_factors: BTreeMap<String, FactorOverride>,
+    #[serde(rename = "repo", default)]
+    repo_factors: BTreeMap<String, FactorOverride>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Weights {
+    name: String,
+    #[serde(rename = "crate")]
+    crate_factors: BTreeMap<String, FactorWeight>,
+    #[serde(rename = "repo")]
+    repo_factors: BTreeMap<String, FactorWeight>,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        let to_map = |defaults: &[(&str, f64, f64)]| defaults
+            .iter()
+            .map(|&(factor, weight, exponent)| (factor.to_string(), FactorWeight::new(weight, exponent)))
+            .collect();
+
+        Self {
+            name: "default".into(),
+            crate_factors: to_map(DEFAULT_CRATE_FACTORS),
+            repo_factors: to_map(DEFAULT_REPO_FACTORS),
+        }
+    }
+}
+
+impl Weights {
+    pub fn from_toml_str(name: &str, toml_str: &str) -> Result<Self> {
+        let file: WeightsFile = toml::from_str(toml_str)?;
+        let mut weights = Self::default();
+        weights.name = file.name.unwrap_or_else(|| name.into());
+
+        Self::apply_overrides("crate", &mut weights.crate_factors, file.crate_factors)?;
+        Self::apply_overrides("repo", &mut weights.repo_factors, file.repo_factors)?;
+        Ok(weights)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let toml_str = fs::read_to_string(path)?;
+        Self::from_toml_str(&path.to_string_lossy(), &toml_str)
+    }
+
+    // Load weights from `path` if passed. Otherwise, from the user's config
+    // dir (e.g. `~/.config/cargo-esr/weights.toml`) if it exists.
+    // Falling back to the built-in default profile.
+    pub fn from_path_or_config(path: Option<&Path>) -> Result<Self> {
+        match (path, Self::config_path()) {
+            (Some(path), _) => Self::from_path(path),
+            (None, Some(ref config_path)) if config_path.is_file() => Self::from_path(config_path),
+            (None, _) => Ok(Self::default()),
+        }
+    }
+
+    pub fn config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("cargo-esr").join("weights.toml"))
+    }
+
+    fn apply_overrides(section: &str,
+                       factors: &mut BTreeMap<String, FactorWeight>,
+                       overrides: BTreeMap<String, FactorOverride>) -> Result<()> {
+        for (factor, ov) in overrides {
+            let fw = factors
+                .get_mut(&factor)
+                .ok_or_else(|| EsrError::Other(format!("Unknown {} factor \"{}\" in weights profile", section, factor)))?;
+
+            fw.weight = ov.weight.unwrap_or(fw.weight);
+            fw.exponent = ov.exponent.unwrap_or(fw.exponent);
+            fw.enabled = ov.enabled.unwrap_or(fw.enabled);
+
+            if !fw.weight.is_finite() {
+                Err(EsrError::Other(format!("Invalid weight {} of {} factor \"{}\" in weights profile",
+                                            fw.weight, section, factor)))?;
+            }
+
+            // Zero or negative exponents turn zero values into infinities
+            if !fw.exponent.is_finite() || fw.exponent <= 0.0 {
+                Err(EsrError::Other(format!("Invalid exponent {} of {} factor \"{}\" in weights profile (must be positive)",
+                                            fw.exponent, section, factor)))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn crate_factors(&self) -> &BTreeMap<String, FactorWeight> {
+        &self.crate_factors
+    }
+
+    pub fn repo_factors(&self) -> &BTreeMap<String, FactorWeight> {
+        &self.repo_factors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_are_validated() {
+        let weights = Weights::from_toml_str("test", "[crate.dependants]\nweight = 2.0\nexponent = 0.25\n").unwrap();
+        let fw = &weights.crate_factors()["dependants"];
+        assert_eq!((weights.get_name(), fw.weight, fw.exponent), ("test", 2.0, 0.25));
+
+        for toml_str in &["[crate.dependants]\nexponent = 0.0\n",
+                          "[repo.subscribers]\nexponent = -0.5\n",
+                          "[repo.subscribers]\nexponent = nan\n",
+                          "[crate.dependants]\nweight = inf\n",
+                          "[crate.no_such_factor]\nweight = 1.0\n"] {
+            assert!(Weights::from_toml_str("test", toml_str).is_err(), "{}", toml_str);
+        }
+    }
+}
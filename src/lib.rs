@@ -11,10 +11,28 @@
 
 #[macro_use]
 mod esr_macros;
-mod esr_errors;
+pub mod esr_errors;
 mod esr_from;
+pub mod esr_cache;
+pub mod esr_ratelimit;
+pub mod esr_retry;
+pub mod esr_config;
 pub mod esr_util;
+pub mod esr_weights;
+pub mod esr_registry;
+pub mod esr_index;
 pub mod esr_crate;
+pub mod esr_deps;
+pub mod esr_repo;
 pub mod esr_github;
+mod esr_github_graphql;
+pub mod esr_gitlab;
+pub mod esr_gitea;
+pub mod esr_git;
 pub mod esr_score;
+pub mod esr_history;
+pub mod esr_policy;
 pub mod esr_printer;
+
+#[cfg(test)]
+mod esr_test_util;